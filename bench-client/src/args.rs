@@ -0,0 +1,279 @@
+use std::time::Duration;
+
+use bench_payload::Content;
+
+use crate::http::Url;
+use crate::proxy::Proxy;
+
+pub const USAGE: &str = "\
+usage: bench-client [OPTIONS] [URL]
+
+Downloads URL (default http://127.0.0.1:$HTTP_SERVER_PORT/bench) and prints
+one JSON line with connect time, time to first byte, throughput and a
+throughput timeline.
+
+options:
+  -x, --proxy URL      socks5://[user:pass@]host[:port], socks5h:// to let
+                       the proxy resolve the server name, or http:// for an
+                       HTTP proxy that forwards absolute-URI requests
+  -p, --proxytunnel    tunnel through an http:// proxy with CONNECT
+  -c, --connections N  download over N connections at once and report the
+                       aggregate, each stream and Jain's fairness index
+  --threads T          spread the connections over T threads, default 1
+  --size SIZE          body size to ask for, e.g. 4GiB
+  --content NAME       zeros, random, pattern or file
+  --seed N             seed for random content, implies --content random
+  --framing F          chunked or length
+  --send PATH          plain, fixed, zerocopy or sendfile
+  --checksum           ask for the XXH64 trailer and compare it
+  --verify             check every body byte against the content
+  --payload-file PATH  the server's PAYLOAD_FILE, to verify --content file
+  --interval SECS      timeline resolution, default 1
+  --duration SECS      measure for this long instead of until the body ends,
+                       e.g. 30s; the server streams until the client hangs up
+  --warmup SECS        with --duration, leave out this much from the start
+  --latency N          send N requests one at a time over one keep-alive
+                       connection and report the latency distribution; the
+                       default URL becomes /ping
+  --churn N            open N connections one after the other, one request
+                       each, and report connections per second and the
+                       connect, proxy handshake and first-byte times; the
+                       default URL becomes /ping
+  --rps                send requests as fast as possible over -c keep-alive
+                       connections for --duration (default 10s) after
+                       --warmup and report requests per second and latency;
+                       the default URL becomes /ping
+  -n, --repeat K       run K times and summarize the throughput with mean,
+                       median, stddev, min/max and a 95% confidence interval
+  --max-cv PCT         flag repeated runs whose coefficient of variation is
+                       above PCT percent, default 5
+";
+
+/// How much longer than the client's window the server is asked to stream, to
+/// cover the time its response takes to arrive.
+const DEADLINE_SLACK: Duration = Duration::from_secs(1);
+
+/// How long `--rps` measures without a `--duration`.
+const DEFAULT_RPS_DURATION: Duration = Duration::from_secs(10);
+
+pub struct Args {
+    /// Target with the options above folded into its query string.
+    pub url: Url,
+    pub proxy: Option<Proxy>,
+    /// Body length asked for, if the client knows it.
+    pub size: Option<u64>,
+    /// Block the body has to repeat when `--verify` is given.
+    pub verify: Option<Vec<u8>>,
+    pub checksum: bool,
+    pub interval: Duration,
+    /// Steady-state window measured after `warmup`, if the run is timed.
+    pub duration: Option<Duration>,
+    pub warmup: Duration,
+    pub connections: usize,
+    pub threads: usize,
+    pub repeat: usize,
+    /// Number of round trips to time instead of downloading.
+    pub latency: Option<usize>,
+    /// Number of connections to open, one request each, instead of
+    /// downloading.
+    pub churn: Option<usize>,
+    /// Send small requests over `connections` keep-alive connections for
+    /// `duration` instead of downloading.
+    pub rps: bool,
+    /// Largest coefficient of variation over repeated runs, as a fraction.
+    pub max_cv: f64,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self, String> {
+        let mut url = None;
+        let mut proxy = None;
+        let mut tunnel = false;
+        let mut query = Vec::new();
+        let mut size = None;
+        let mut content = None;
+        let mut verify = false;
+        let mut checksum = false;
+        let mut payload_file = None;
+        let mut interval = Duration::from_secs(1);
+        let mut duration = None;
+        let mut warmup = None;
+        let mut connections = 1;
+        let mut threads = 1;
+        let mut repeat = 1;
+        let mut latency = None;
+        let mut churn = None;
+        let mut rps = false;
+        let mut max_cv = 0.05;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+            match arg.as_str() {
+                "-h" | "--help" => return Err(USAGE.into()),
+                "-x" | "--proxy" => proxy = Some(value()?),
+                "-p" | "--proxytunnel" => tunnel = true,
+                "-c" | "--connections" => connections = positive(&arg, value()?)?,
+                "--threads" => threads = positive(&arg, value()?)?,
+                "--size" => {
+                    let s = value()?;
+                    size = Some(bench_payload::parse_size(&s).ok_or(format!("bad size {s:?}"))?);
+                    query.push(format!("size={}", size.unwrap_or_default()));
+                }
+                "--content" => {
+                    let name = value()?;
+                    content =
+                        Some(Content::parse(&name).ok_or(format!("unknown content {name:?}"))?);
+                }
+                "--seed" => {
+                    let seed = value()?;
+                    content = Some(Content::Random(
+                        seed.parse().map_err(|_| format!("bad seed {seed:?}"))?,
+                    ));
+                }
+                "--framing" => query.push(format!("framing={}", value()?)),
+                "--send" => query.push(format!("send={}", value()?)),
+                "--checksum" => {
+                    checksum = true;
+                    query.push("checksum=xxh64".into());
+                }
+                "--verify" => verify = true,
+                "--payload-file" => payload_file = Some(value()?),
+                "--interval" => {
+                    let secs = value()?;
+                    interval = secs
+                        .parse()
+                        .ok()
+                        .filter(|&s: &f64| s > 0.0)
+                        .map(Duration::from_secs_f64)
+                        .ok_or(format!("bad interval {secs:?}"))?;
+                }
+                "--duration" => {
+                    let d = value()?;
+                    duration = Some(
+                        bench_payload::parse_duration(&d)
+                            .filter(|d| !d.is_zero())
+                            .ok_or(format!("bad duration {d:?}"))?,
+                    );
+                }
+                "--warmup" => {
+                    let d = value()?;
+                    warmup =
+                        Some(bench_payload::parse_duration(&d).ok_or(format!("bad warmup {d:?}"))?);
+                }
+                "--latency" => latency = Some(positive(&arg, value()?)?),
+                "--churn" => churn = Some(positive(&arg, value()?)?),
+                "--rps" => rps = true,
+                "-n" | "--repeat" => repeat = positive(&arg, value()?)?,
+                "--max-cv" => {
+                    let pct = value()?;
+                    max_cv = pct
+                        .trim_end_matches('%')
+                        .parse()
+                        .ok()
+                        .filter(|&p: &f64| p >= 0.0)
+                        .map(|p| p / 100.0)
+                        .ok_or(format!("bad --max-cv {pct:?}"))?;
+                }
+                s if s.starts_with('-') => return Err(format!("unknown option {s:?}\n\n{USAGE}")),
+                s if url.is_none() => url = Some(Url::parse(s).ok_or(format!("bad url {s:?}"))?),
+                s => return Err(format!("unexpected argument {s:?}")),
+            }
+        }
+
+        let modes = [latency.is_some(), churn.is_some(), rps]
+            .iter()
+            .filter(|&&m| m)
+            .count();
+        if modes > 1 {
+            return Err("--latency, --churn and --rps cannot be combined".into());
+        }
+        if modes > 0 && repeat > 1 {
+            return Err("-n repeats downloads only".into());
+        }
+        if (latency.is_some() || churn.is_some()) && connections > 1 {
+            return Err("--latency and --churn make one request at a time, without -c".into());
+        }
+        if rps {
+            duration = duration.or(Some(DEFAULT_RPS_DURATION));
+        }
+
+        match duration {
+            Some(_) if checksum => {
+                return Err("--checksum needs the whole body, not a --duration".into());
+            }
+            Some(_) if latency.is_some() || churn.is_some() => {
+                return Err("--duration is for downloads and --rps".into());
+            }
+            Some(duration) if !rps => {
+                let deadline = warmup.unwrap_or_default() + duration + DEADLINE_SLACK;
+                query.push(format!("duration={}ms", deadline.as_millis()));
+            }
+            Some(_) => {}
+            None if warmup.is_some() => return Err("--warmup needs --duration".into()),
+            None => {}
+        }
+
+        let proxy = match proxy {
+            Some(s) => Some(Proxy::parse(&s, tunnel).ok_or(format!("bad proxy {s:?}"))?),
+            None => None,
+        };
+
+        let mut url = match url {
+            Some(url) => url,
+            None => {
+                let port = std::env::var("HTTP_SERVER_PORT").unwrap_or_else(|_| "8089".into());
+                let path = if modes > 0 { "ping" } else { "bench" };
+                let url = format!("http://127.0.0.1:{port}/{path}");
+                Url::parse(&url).ok_or(format!("bad HTTP_SERVER_PORT {port:?}"))?
+            }
+        };
+        if let Some(content) = content {
+            query.push(content.to_string());
+        }
+        for pair in &query {
+            url.push_query(pair);
+        }
+
+        let verify = if verify {
+            let content = content.unwrap_or(Content::Zeros);
+            let block = match (content.block(), payload_file) {
+                (Some(block), _) => block,
+                (None, Some(path)) => std::fs::read(&path).map_err(|e| format!("{path}: {e}"))?,
+                (None, None) => return Err("--verify of file content needs --payload-file".into()),
+            };
+            if block.is_empty() {
+                return Err("payload file is empty".into());
+            }
+            Some(block)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            url,
+            proxy,
+            size,
+            verify,
+            checksum,
+            interval,
+            duration,
+            warmup: warmup.unwrap_or_default(),
+            connections,
+            threads,
+            repeat,
+            latency,
+            churn,
+            rps,
+            max_cv,
+        })
+    }
+}
+
+fn positive(arg: &str, value: String) -> Result<usize, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or(format!("{arg} needs a positive number, not {value:?}"))
+}
@@ -0,0 +1,59 @@
+use std::time::Instant;
+
+use anyhow::Context as _;
+use compio::io::AsyncWriteExt as _;
+
+use crate::args::Args;
+use crate::histogram::Histogram;
+use crate::proxy::{self, Proxy};
+use crate::report::Churn;
+
+/// Requests `args.url` `connections` times, one after the other, each over a
+/// fresh connection through the proxy that is closed after the response.
+pub async fn run(args: &Args, connections: usize) -> anyhow::Result<Churn> {
+    let request = proxy::get(&args.url, args.proxy.as_ref());
+    let mut connect = Histogram::new();
+    let mut handshake = Histogram::new();
+    let mut ttfb = Histogram::new();
+    let mut total = Histogram::new();
+
+    let start = Instant::now();
+    for i in 1..=connections {
+        let began = Instant::now();
+        let connected = proxy::connect(&args.url, args.proxy.as_ref())
+            .await
+            .with_context(|| format!("connection {i}"))?;
+        let mut conn = connected.conn;
+        let sent = Instant::now();
+        conn.stream.write_all(request.clone()).await.0?;
+        let resp = conn
+            .read_response()
+            .await
+            .with_context(|| format!("connection {i}"))?;
+        let head_at = Instant::now();
+        conn.read_body(resp.body()?, &mut |_| {}).await?;
+        let done = Instant::now();
+        _ = conn.stream.close().await;
+
+        if resp.status != 200 {
+            anyhow::bail!("connection {i}: {} {}", resp.status, resp.reason);
+        }
+        connect.record(connected.connect);
+        if let Some(d) = connected.handshake {
+            handshake.record(d);
+        }
+        ttfb.record(head_at - sent);
+        total.record(done - began);
+    }
+
+    Ok(Churn {
+        url: args.url.to_string(),
+        proxy: args.proxy.as_ref().map(ToString::to_string),
+        proxy_mode: args.proxy.as_ref().map(Proxy::mode),
+        elapsed: start.elapsed(),
+        connect,
+        handshake: (handshake.count() > 0).then_some(handshake),
+        ttfb,
+        total,
+    })
+}
@@ -0,0 +1,141 @@
+use std::time::Instant;
+
+use bench_payload::{Verifier, Xxh64};
+use compio::io::AsyncWriteExt as _;
+
+use crate::args::Args;
+use crate::http::{self, Body};
+use crate::proxy::{self, Proxy};
+use crate::report::{Integrity, Timeline, Transfer, Window};
+
+/// What the body is checked with while it streams in.
+enum Check {
+    None,
+    Content(Verifier),
+    Checksum(Xxh64),
+}
+
+impl Check {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Check::None => {}
+            Check::Content(verifier) => verifier.update(data),
+            Check::Checksum(hasher) => hasher.update(data),
+        }
+    }
+}
+
+/// Downloads `args.url` once over a fresh connection. The timeline counts
+/// from `origin`, or from the response head if there is none. With
+/// `--duration` the connection is closed once the window after the warm-up is
+/// over.
+pub async fn run(args: &Args, origin: Option<Instant>) -> anyhow::Result<Transfer> {
+    let connected = proxy::connect(&args.url, args.proxy.as_ref()).await?;
+
+    let mut conn = connected.conn;
+    let request = proxy::get(&args.url, args.proxy.as_ref());
+    conn.stream.write_all(request).await.0?;
+    let sent = Instant::now();
+
+    let resp = conn.read_response().await?;
+    let head_at = Instant::now();
+    let body = resp.body()?;
+    if resp.status != 200 {
+        let mut message = Vec::new();
+        _ = conn
+            .read_body(body, &mut |data| message.extend_from_slice(data))
+            .await;
+        let message = String::from_utf8_lossy(&message);
+        match message.trim() {
+            "" => anyhow::bail!("{} {}", resp.status, resp.reason),
+            message => anyhow::bail!("{} {}: {message}", resp.status, resp.reason),
+        }
+    }
+
+    let mut check = match &args.verify {
+        Some(block) => Check::Content(Verifier::new(block.clone())),
+        None if args.checksum => Check::Checksum(Xxh64::new(0)),
+        None => Check::None,
+    };
+    let mut timeline = Timeline::new(origin.unwrap_or(head_at), args.interval);
+    let window = args.duration.map(|d| {
+        let start = head_at + args.warmup;
+        (start, start + d)
+    });
+    let mut bytes = 0;
+    let mut window_bytes = 0;
+    let mut sink = |data: &[u8]| {
+        let now = Instant::now();
+        timeline.record(now, data.len() as u64);
+        bytes += data.len() as u64;
+        if window.is_some_and(|(start, end)| (start..end).contains(&now)) {
+            window_bytes += data.len() as u64;
+        }
+        check.update(data);
+    };
+    let read = conn.read_body(body, &mut sink);
+    let (trailers, cut_short) = match window {
+        Some((_, end)) => match compio::time::timeout_at(end, read).await {
+            Ok(trailers) => (trailers?, false),
+            Err(_) => (Vec::new(), true),
+        },
+        None => (read.await?, false),
+    };
+    let done = Instant::now();
+    _ = conn.stream.close().await;
+
+    let server_checksum = http::header(&trailers, bench_payload::CHECKSUM_TRAILER)
+        .and_then(bench_payload::parse_checksum);
+    if args.checksum && server_checksum.is_none() {
+        anyhow::bail!(
+            "no {} trailer in the response",
+            bench_payload::CHECKSUM_TRAILER
+        );
+    }
+    let expected = match body {
+        _ if cut_short => None,
+        Body::Length(len) => Some(len),
+        _ => args.size,
+    };
+    let integrity = match check {
+        Check::None => None,
+        Check::Content(verifier) => {
+            let verdict = verifier.finish();
+            Some(Integrity {
+                verdict: Some(verdict),
+                checksum: verdict.checksum,
+                server_checksum,
+                intact: verdict.is_intact(expected.unwrap_or(bytes), server_checksum),
+            })
+        }
+        Check::Checksum(hasher) => {
+            let checksum = hasher.finish();
+            Some(Integrity {
+                verdict: None,
+                checksum,
+                server_checksum,
+                intact: expected.is_none_or(|len| len == bytes)
+                    && server_checksum == Some(checksum),
+            })
+        }
+    };
+
+    Ok(Transfer {
+        url: args.url.to_string(),
+        proxy: args.proxy.as_ref().map(ToString::to_string),
+        proxy_mode: args.proxy.as_ref().map(Proxy::mode),
+        status: resp.status,
+        connect: connected.connect,
+        handshake: connected.handshake,
+        ttfb: head_at - sent,
+        transfer: done - head_at,
+        bytes,
+        window: window.map(|(start, end)| Window {
+            warmup: args.warmup,
+            measured: end.min(done).saturating_duration_since(start),
+            bytes: window_bytes,
+        }),
+        timeline,
+        integrity,
+    })
+}
@@ -0,0 +1,261 @@
+//! Latency histogram in the layout of HdrHistogram: linear sub-buckets within
+//! each power of two, so every recorded value keeps three significant digits
+//! however large it is, at a fixed memory cost.
+
+use std::time::Duration;
+
+use bench_payload::json::{Object, ToJson, Value};
+
+/// Values below `2 * SUB_BUCKETS` nanoseconds are counted exactly; above
+/// that each power of two is split into `SUB_BUCKETS` equal slots.
+const SUB_BUCKETS: u64 = 1024;
+const SUB_BUCKET_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            counts: Vec::new(),
+            total: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        let i = index(nanos);
+        if self.counts.len() <= i {
+            self.counts.resize(i + 1, 0);
+        }
+        self.counts[i] += 1;
+        self.total += 1;
+        self.sum += u128::from(nanos);
+        self.min = self.min.min(nanos);
+        self.max = self.max.max(nanos);
+    }
+
+    /// Adds every value recorded in `other`.
+    pub fn merge(&mut self, other: &Histogram) {
+        if self.counts.len() < other.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.total += other.total;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.total {
+            0 => Duration::ZERO,
+            n => Duration::from_nanos((self.sum / u128::from(n)) as u64),
+        }
+    }
+
+    pub fn min(&self) -> Duration {
+        Duration::from_nanos(if self.total == 0 { 0 } else { self.min })
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    /// The value `percentile`% of the recorded values are at or below, as the
+    /// highest value of its slot, capped at the largest value recorded.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.total == 0 {
+            return Duration::ZERO;
+        }
+        // Multiplied first so that e.g. 99.9% of 1000 is exactly 999.
+        let rank = ((percentile * self.total as f64 / 100.0).ceil() as u64).clamp(1, self.total);
+        let mut seen = 0;
+        for (i, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(highest_equivalent(i).min(self.max));
+            }
+        }
+        self.max()
+    }
+
+    /// Counts per power of two of microseconds, `below_us` being each range's
+    /// exclusive upper bound, without the empty ranges at either end.
+    fn octaves(&self) -> Vec<(u64, u64)> {
+        let mut octaves: Vec<(u64, u64)> = Vec::new();
+        for (i, &count) in self.counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let micros = highest_equivalent(i) / 1000;
+            let below = (micros + 1).next_power_of_two();
+            match octaves.last_mut() {
+                Some((last, n)) if *last == below => *n += count,
+                _ => {
+                    // Keep the ranges contiguous so gaps show up as zeros.
+                    while let Some(&(last, _)) = octaves.last()
+                        && last * 2 < below
+                    {
+                        octaves.push((last * 2, 0));
+                    }
+                    octaves.push((below, count));
+                }
+            }
+        }
+        octaves
+    }
+}
+
+/// Slot of a value in nanoseconds.
+fn index(nanos: u64) -> usize {
+    if nanos < 2 * SUB_BUCKETS {
+        return nanos as usize;
+    }
+    let shift = (63 - nanos.leading_zeros()) - SUB_BUCKET_BITS;
+    let sub = (nanos >> shift) - SUB_BUCKETS;
+    ((u64::from(shift) + 1) * SUB_BUCKETS + sub) as usize
+}
+
+/// Largest value in nanoseconds that falls into slot `i`.
+fn highest_equivalent(i: usize) -> u64 {
+    let i = i as u64;
+    if i < 2 * SUB_BUCKETS {
+        return i;
+    }
+    let shift = i / SUB_BUCKETS - 1;
+    let sub = i % SUB_BUCKETS + SUB_BUCKETS;
+    // Not `((sub + 1) << shift) - 1`, which overflows for the top slot.
+    (sub << shift) | ((1 << shift) - 1)
+}
+
+fn micros(d: Duration) -> f64 {
+    d.as_secs_f64() * 1e6
+}
+
+impl ToJson for Histogram {
+    fn to_json(&self) -> Value {
+        let octaves: Vec<_> = self
+            .octaves()
+            .into_iter()
+            .map(|(below, count)| {
+                Object::new()
+                    .field("below_us", &below)
+                    .field("count", &count)
+            })
+            .collect();
+        Object::new()
+            .field("count", &self.total)
+            .field("min_us", &micros(self.min()))
+            .field("mean_us", &micros(self.mean()))
+            .field("p50_us", &micros(self.percentile(50.0)))
+            .field("p90_us", &micros(self.percentile(90.0)))
+            .field("p99_us", &micros(self.percentile(99.0)))
+            .field("p999_us", &micros(self.percentile(99.9)))
+            .field("max_us", &micros(self.max()))
+            .field("histogram", &octaves)
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_values_are_exact() {
+        for nanos in [0, 1, 999, 2 * SUB_BUCKETS - 1] {
+            assert_eq!(index(nanos), nanos as usize);
+            assert_eq!(highest_equivalent(nanos as usize), nanos);
+        }
+    }
+
+    #[test]
+    fn slot_boundaries() {
+        // From 2048 on, slots are two nanoseconds wide, then four from 4096.
+        assert_eq!(index(2048), 2048);
+        assert_eq!(index(2049), 2048);
+        assert_eq!(index(2050), 2049);
+        assert_eq!(highest_equivalent(2048), 2049);
+        assert_eq!(index(4095), 3071);
+        assert_eq!(index(4096), 3072);
+        assert_eq!(highest_equivalent(3072), 4099);
+        assert_eq!(index(u64::MAX), 55 * SUB_BUCKETS as usize - 1);
+        assert_eq!(highest_equivalent(index(u64::MAX)), u64::MAX);
+    }
+
+    #[test]
+    fn slots_are_contiguous_and_precise() {
+        let values = (0..64)
+            .flat_map(|bit| {
+                let p = 1u64 << bit;
+                [p - 1, p, p + 1, p + p / 3]
+            })
+            .chain([1_000, 123_456, 1_000_000_007, u64::MAX / 3]);
+        for nanos in values {
+            let i = index(nanos);
+            let highest = highest_equivalent(i);
+            let lowest = if i == 0 {
+                0
+            } else {
+                highest_equivalent(i - 1) + 1
+            };
+            assert!((lowest..=highest).contains(&nanos), "{nanos} in slot {i}");
+            assert_eq!(index(lowest), i);
+            if highest < u64::MAX {
+                assert_eq!(index(highest + 1), i + 1);
+            }
+            // Three significant digits: the slot is under 0.1% of the value.
+            assert!((highest - lowest) as f64 <= nanos as f64 / SUB_BUCKETS as f64);
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut histogram = Histogram::new();
+        assert_eq!(histogram.percentile(50.0), Duration::ZERO);
+        for micros in 1..=1000 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        for (percentile, micros) in [(50.0, 500), (99.0, 990), (99.9, 999), (100.0, 1000)] {
+            let value = histogram.percentile(percentile).as_nanos() as f64;
+            let expected = micros as f64 * 1000.0;
+            assert!(
+                value >= expected && value <= expected * 1.001,
+                "p{percentile}: {value}"
+            );
+        }
+        assert_eq!(histogram.min(), Duration::from_micros(1));
+        assert_eq!(histogram.max(), Duration::from_micros(1000));
+        assert_eq!(histogram.mean(), Duration::from_nanos(500_500));
+    }
+
+    #[test]
+    fn merge_and_octaves() {
+        let mut a = Histogram::new();
+        a.record(Duration::from_micros(3));
+        let mut b = Histogram::new();
+        b.record(Duration::from_micros(40));
+        b.record(Duration::from_micros(50));
+        a.merge(&b);
+        assert_eq!(a.count(), 3);
+        assert_eq!(a.min(), Duration::from_micros(3));
+        assert_eq!(a.max(), Duration::from_micros(50));
+        // The empty ranges between 4 and 64 us show up as zeros.
+        assert_eq!(a.octaves(), [(4, 1), (8, 0), (16, 0), (32, 0), (64, 2)]);
+    }
+}
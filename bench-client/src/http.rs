@@ -0,0 +1,373 @@
+//! Just enough of an HTTP/1.1 client to drive fast-server.
+
+use std::{fmt, io};
+
+use bench_payload::http::{self as head, LineError, MAX_HEAD_SIZE, READ_SIZE};
+use compio::{
+    BufResult,
+    buf::{IntoInner as _, IoBuf as _},
+    io::AsyncRead as _,
+    net::TcpStream,
+};
+
+/// Body bytes are read straight into this much scratch space.
+const BODY_READ_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Malformed(&'static str),
+    HeadTooLarge,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {e}"),
+            Error::Malformed(what) => write!(f, "malformed response: {what}"),
+            Error::HeadTooLarge => write!(f, "response head exceeds {MAX_HEAD_SIZE} bytes"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<LineError> for Error {
+    fn from(e: LineError) -> Self {
+        match e {
+            LineError::Io(e) => Error::Io(e),
+            LineError::TooLong => Error::HeadTooLarge,
+            LineError::NotUtf8 => Error::Malformed("non utf-8 line"),
+            LineError::Closed => Error::Malformed("connection closed mid-body"),
+        }
+    }
+}
+
+/// An `http://` URL split into what the client needs.
+#[derive(Debug, Clone)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    /// Origin-form request target, path plus query.
+    pub target: String,
+}
+
+impl Url {
+    pub fn parse(s: &str) -> Option<Self> {
+        let rest = s.strip_prefix("http://")?;
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let target = match target.split_once('#') {
+            Some((target, _)) => target,
+            None => target,
+        };
+        let target = if target.starts_with('?') {
+            format!("/{target}")
+        } else {
+            target.to_owned()
+        };
+        let (host, port) = split_host_port(authority)?;
+        Some(Self {
+            host: host.to_owned(),
+            port: port.unwrap_or(80),
+            target,
+        })
+    }
+
+    /// `host:port` as sent in the `Host` header, with IPv6 hosts in brackets.
+    pub fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    /// Appends `key=value` to the query string.
+    pub fn push_query(&mut self, pair: &str) {
+        let sep = if self.target.contains('?') { '&' } else { '?' };
+        self.target.push(sep);
+        self.target.push_str(pair);
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority(), self.target)
+    }
+}
+
+/// Splits `host`, `host:port`, `[v6]` or `[v6]:port`.
+pub fn split_host_port(authority: &str) -> Option<(&str, Option<u16>)> {
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest.split_once(']')?;
+            (host, rest.strip_prefix(':'))
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let port = match port {
+        Some(port) => Some(port.parse().ok()?),
+        None => None,
+    };
+    Some((host, port))
+}
+
+/// A request head without a body, such as `GET` or `CONNECT`.
+pub fn request(method: &str, target: &str, host: &str, headers: &[(&str, &str)]) -> String {
+    use fmt::Write as _;
+
+    let mut head = format!(
+        "{method} {target} HTTP/1.1\r\nHost: {host}\r\nUser-Agent: bench-client\r\nAccept: */*\r\n"
+    );
+    for (name, value) in headers {
+        _ = write!(head, "{name}: {value}\r\n");
+    }
+    head + "\r\n"
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Body {
+    Length(u64),
+    Chunked,
+    /// Everything up to the end of the connection.
+    Close,
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Response {
+    /// First header named `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+
+    /// Whether the connection stays open for another request afterwards.
+    pub fn keeps_alive(&self) -> bool {
+        let close = self
+            .header("connection")
+            .is_some_and(|c| c.eq_ignore_ascii_case("close"));
+        !close && !matches!(self.body(), Ok(Body::Close))
+    }
+
+    pub fn body(&self) -> Result<Body, Error> {
+        let chunked = self
+            .header("transfer-encoding")
+            .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+        if chunked {
+            return Ok(Body::Chunked);
+        }
+        match self.header("content-length") {
+            Some(len) => len
+                .parse()
+                .map(Body::Length)
+                .map_err(|_| Error::Malformed("content-length")),
+            None => Ok(Body::Close),
+        }
+    }
+}
+
+pub fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+pub struct Conn {
+    pub stream: TcpStream,
+    /// Bytes read past the last response head or line.
+    buf: Vec<u8>,
+    scratch: Vec<u8>,
+}
+
+impl Conn {
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            buf: Vec::with_capacity(READ_SIZE),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Reads the next final response head, skipping `1xx` interim responses.
+    pub async fn read_response(&mut self) -> Result<Response, Error> {
+        loop {
+            if let Some((resp, len)) = parse_response(&self.buf)? {
+                self.buf.drain(..len);
+                if (100..200).contains(&resp.status) && resp.status != 101 {
+                    continue;
+                }
+                return Ok(resp);
+            }
+            if self.buf.len() >= MAX_HEAD_SIZE {
+                return Err(Error::HeadTooLarge);
+            }
+            if self.fill().await? == 0 {
+                return Err(Error::Malformed("connection closed before the response"));
+            }
+        }
+    }
+
+    /// Reads a body framed as `body`, handing the data to `sink` as it
+    /// arrives. Returns the trailer fields of a chunked body.
+    pub async fn read_body(
+        &mut self,
+        body: Body,
+        sink: &mut impl FnMut(&[u8]),
+    ) -> Result<Vec<(String, String)>, Error> {
+        match body {
+            Body::Length(len) => self.read_exact(len, sink).await.map(|()| Vec::new()),
+            Body::Chunked => self.read_chunked(sink).await,
+            Body::Close => self.read_to_end(sink).await.map(|()| Vec::new()),
+        }
+    }
+
+    /// Reads exactly `len` bytes. Whatever is not buffered yet is read into
+    /// scratch space, never past the end of the body.
+    async fn read_exact(&mut self, len: u64, sink: &mut impl FnMut(&[u8])) -> Result<(), Error> {
+        let buffered = len.min(self.buf.len() as u64) as usize;
+        if buffered > 0 {
+            sink(&self.buf[..buffered]);
+            self.buf.drain(..buffered);
+        }
+        let mut left = len - buffered as u64;
+        if left == 0 {
+            return Ok(());
+        }
+
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.reserve(BODY_READ_SIZE);
+        let result = loop {
+            let want = left.min(scratch.capacity() as u64) as usize;
+            let BufResult(result, slice) = self.stream.read(scratch.slice(..want)).await;
+            scratch = slice.into_inner();
+            match result {
+                Ok(0) => break Err(Error::Malformed("connection closed mid-body")),
+                Ok(n) => {
+                    sink(&scratch[..n]);
+                    left -= n as u64;
+                }
+                Err(e) => break Err(e.into()),
+            }
+            if left == 0 {
+                break Ok(());
+            }
+        };
+        self.scratch = scratch;
+        result
+    }
+
+    async fn read_chunked(
+        &mut self,
+        sink: &mut impl FnMut(&[u8]),
+    ) -> Result<Vec<(String, String)>, Error> {
+        loop {
+            let line = self.read_line().await?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = u64::from_str_radix(size, 16).map_err(|_| Error::Malformed("chunk size"))?;
+            if size == 0 {
+                let mut trailers = Vec::new();
+                loop {
+                    let line = self.read_line().await?;
+                    if line.is_empty() {
+                        return Ok(trailers);
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        trailers.push((name.trim().to_owned(), value.trim().to_owned()));
+                    }
+                }
+            }
+            self.read_exact(size, sink).await?;
+            if !self.read_line().await?.is_empty() {
+                return Err(Error::Malformed("chunk data"));
+            }
+        }
+    }
+
+    async fn read_to_end(&mut self, sink: &mut impl FnMut(&[u8])) -> Result<(), Error> {
+        if !self.buf.is_empty() {
+            sink(&self.buf);
+            self.buf.clear();
+        }
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.reserve(BODY_READ_SIZE);
+        let result = loop {
+            let want = scratch.capacity();
+            let BufResult(result, slice) = self.stream.read(scratch.slice(..want)).await;
+            scratch = slice.into_inner();
+            match result {
+                Ok(0) => break Ok(()),
+                Ok(n) => sink(&scratch[..n]),
+                Err(e) => break Err(e.into()),
+            }
+        };
+        self.scratch = scratch;
+        result
+    }
+
+    async fn read_line(&mut self) -> Result<String, Error> {
+        Ok(head::read_line(&mut self.stream, &mut self.buf).await?)
+    }
+
+    async fn fill(&mut self) -> io::Result<usize> {
+        head::fill(&mut self.stream, &mut self.buf).await
+    }
+}
+
+/// Parses one response head from the start of `buf`. Returns the response and
+/// the number of bytes it occupied, or `None` if the head is not complete.
+fn parse_response(buf: &[u8]) -> Result<Option<(Response, usize)>, Error> {
+    let Some(end) = head::find_head_end(buf) else {
+        return Ok(None);
+    };
+    let head = std::str::from_utf8(&buf[..end]).map_err(|_| Error::Malformed("non utf-8 head"))?;
+    let mut lines = head.split('\n').map(|l| l.strip_suffix('\r').unwrap_or(l));
+
+    let status_line = lines.next().unwrap_or_default();
+    let mut parts = status_line.splitn(3, ' ');
+    let (Some(version), Some(status)) = (parts.next(), parts.next()) else {
+        return Err(Error::Malformed("status line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(Error::Malformed("http version"));
+    }
+    let status = status
+        .parse()
+        .map_err(|_| Error::Malformed("status code"))?;
+    let reason = parts.next().unwrap_or_default().to_owned();
+
+    let mut headers = Vec::new();
+    for line in lines.take_while(|l| !l.is_empty()) {
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(Error::Malformed("header line"));
+        };
+        headers.push((name.to_owned(), value.trim_matches([' ', '\t']).to_owned()));
+    }
+
+    let resp = Response {
+        status,
+        reason,
+        headers,
+    };
+    Ok(Some((resp, end)))
+}
@@ -0,0 +1,51 @@
+use std::time::Instant;
+
+use anyhow::Context as _;
+use compio::io::AsyncWriteExt as _;
+
+use crate::args::Args;
+use crate::histogram::Histogram;
+use crate::proxy::{self, Proxy};
+use crate::report::Latency;
+
+/// Sends `requests` requests for `args.url` one at a time over one keep-alive
+/// connection, timing each from writing the request until the end of the
+/// response body.
+pub async fn run(args: &Args, requests: usize) -> anyhow::Result<Latency> {
+    let connected = proxy::connect(&args.url, args.proxy.as_ref()).await?;
+
+    let mut conn = connected.conn;
+    let request = proxy::get(&args.url, args.proxy.as_ref());
+    let mut histogram = Histogram::new();
+    let start = Instant::now();
+    for i in 1..=requests {
+        let sent = Instant::now();
+        conn.stream.write_all(request.clone()).await.0?;
+        let resp = conn
+            .read_response()
+            .await
+            .with_context(|| format!("request {i}"))?;
+        let body = resp.body()?;
+        conn.read_body(body, &mut |_| {}).await?;
+        histogram.record(sent.elapsed());
+
+        if resp.status != 200 {
+            anyhow::bail!("request {i}: {} {}", resp.status, resp.reason);
+        }
+        if !resp.keeps_alive() && i < requests {
+            anyhow::bail!("server closed the connection after {i} requests");
+        }
+    }
+    let elapsed = start.elapsed();
+    _ = conn.stream.close().await;
+
+    Ok(Latency {
+        url: args.url.to_string(),
+        proxy: args.proxy.as_ref().map(ToString::to_string),
+        proxy_mode: args.proxy.as_ref().map(Proxy::mode),
+        connect: connected.connect,
+        handshake: connected.handshake,
+        elapsed,
+        histogram,
+    })
+}
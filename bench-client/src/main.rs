@@ -0,0 +1,95 @@
+mod args;
+mod churn;
+mod download;
+mod histogram;
+mod http;
+mod latency;
+mod parallel;
+mod proxy;
+mod report;
+mod rps;
+mod socks;
+
+use std::process::ExitCode;
+
+use anyhow::Context as _;
+
+use crate::args::Args;
+use crate::report::{Repeated, Run};
+use bench_payload::json::ToJson as _;
+
+#[compio::main]
+async fn main() -> ExitCode {
+    _ = dotenvy::dotenv();
+
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = if let Some(requests) = args.latency {
+        latency::run(&args, requests)
+            .await
+            .map(|latency| (latency.to_json(), true))
+    } else if let Some(connections) = args.churn {
+        churn::run(&args, connections)
+            .await
+            .map(|churn| (churn.to_json(), true))
+    } else if args.rps {
+        rps::run(&args).await.map(|rps| (rps.to_json(), true))
+    } else if args.repeat > 1 {
+        repeat(&args).await.map(|repeated| {
+            if !repeated.is_stable() {
+                let summary = repeated.summary();
+                eprintln!(
+                    "bench-client: unstable result, {:.1} MiB/s varies by {:.1}% over {} runs (max {:.1}%)",
+                    summary.mean,
+                    summary.cv() * 100.0,
+                    summary.n,
+                    repeated.max_cv * 100.0
+                );
+            }
+            (repeated.to_json(), repeated.is_intact())
+        })
+    } else {
+        run(&args).await.map(|run| (run.to_json(), run.is_intact()))
+    };
+
+    match result {
+        Ok((json, intact)) => {
+            println!("{json}");
+            if intact {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => {
+            eprintln!("bench-client: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: &Args) -> anyhow::Result<Run> {
+    if args.connections > 1 {
+        parallel::run(args).await.map(Run::Parallel)
+    } else {
+        download::run(args, None).await.map(Run::Single)
+    }
+}
+
+/// Runs the same download `args.repeat` times, one after the other.
+async fn repeat(args: &Args) -> anyhow::Result<Repeated> {
+    let mut runs = Vec::with_capacity(args.repeat);
+    for i in 0..args.repeat {
+        runs.push(run(args).await.with_context(|| format!("run {}", i + 1))?);
+    }
+    Ok(Repeated {
+        runs,
+        max_cv: args.max_cv,
+    })
+}
@@ -0,0 +1,53 @@
+use std::thread;
+use std::time::Instant;
+
+use anyhow::Context as _;
+use futures_util::future::join_all;
+
+use crate::args::Args;
+use crate::download;
+use crate::report::{Aggregate, Timeline, Transfer};
+
+/// Runs `args.connections` downloads at once, spread round-robin over
+/// `args.threads` compio runtimes, each on its own thread.
+pub async fn run(args: &Args) -> anyhow::Result<Aggregate> {
+    let origin = Instant::now();
+    let results = if args.threads <= 1 {
+        join_all((0..args.connections).map(|_| download::run(args, Some(origin)))).await
+    } else {
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..args.threads)
+                .map(|t| {
+                    let count = (t..args.connections).step_by(args.threads).count();
+                    scope.spawn(move || {
+                        let runtime = compio::runtime::Runtime::new()?;
+                        let downloads = (0..count).map(|_| download::run(args, Some(origin)));
+                        anyhow::Ok(runtime.block_on(join_all(downloads)))
+                    })
+                })
+                .collect();
+            let mut results = Vec::new();
+            for worker in workers {
+                let worker = worker
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("client thread panicked"))?;
+                results.extend(worker?);
+            }
+            anyhow::Ok(results)
+        })?
+    };
+    let wall = origin.elapsed();
+
+    let streams = results
+        .into_iter()
+        .enumerate()
+        .map(|(i, result)| result.with_context(|| format!("stream {i}")))
+        .collect::<anyhow::Result<Vec<Transfer>>>()?;
+    let timeline = Timeline::sum(origin, args.interval, streams.iter().map(|s| &s.timeline));
+    Ok(Aggregate {
+        threads: args.threads.max(1),
+        wall,
+        timeline,
+        streams,
+    })
+}
@@ -0,0 +1,539 @@
+use std::time::{Duration, Instant};
+
+use bench_payload::Verdict;
+
+use crate::histogram::Histogram;
+use bench_payload::json::{Object, ToJson, Value};
+use bench_payload::stats::Summary;
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+/// Bytes received per fixed interval, counted from `start`.
+pub struct Timeline {
+    start: Instant,
+    interval: Duration,
+    buckets: Vec<u64>,
+    last: Instant,
+}
+
+impl Timeline {
+    pub fn new(start: Instant, interval: Duration) -> Self {
+        Self {
+            start,
+            interval,
+            buckets: Vec::new(),
+            last: start,
+        }
+    }
+
+    pub fn record(&mut self, at: Instant, bytes: u64) {
+        let elapsed = at.saturating_duration_since(self.start);
+        let i = (elapsed.as_secs_f64() / self.interval.as_secs_f64()) as usize;
+        if self.buckets.len() <= i {
+            self.buckets.resize(i + 1, 0);
+        }
+        self.buckets[i] += bytes;
+        self.last = self.last.max(at);
+    }
+
+    /// Adds up timelines that share a start and interval.
+    pub fn sum<'a>(
+        start: Instant,
+        interval: Duration,
+        timelines: impl Iterator<Item = &'a Timeline>,
+    ) -> Self {
+        let mut sum = Self::new(start, interval);
+        for timeline in timelines {
+            debug_assert!(timeline.start == start && timeline.interval == interval);
+            if sum.buckets.len() < timeline.buckets.len() {
+                sum.buckets.resize(timeline.buckets.len(), 0);
+            }
+            for (total, bytes) in sum.buckets.iter_mut().zip(&timeline.buckets) {
+                *total += bytes;
+            }
+            sum.last = sum.last.max(timeline.last);
+        }
+        sum
+    }
+}
+
+/// A last bucket shorter than this fraction of the interval is folded into
+/// the one before it, so a few bytes over a sliver of time do not show up as
+/// a spike.
+const MIN_TAIL: f64 = 0.25;
+
+/// One entry per interval; the last one only covers the time up to the last
+/// byte, so its rate is not diluted by the unused rest of the interval.
+/// `duration_secs` is how long each entry actually covers.
+impl ToJson for Timeline {
+    fn to_json(&self) -> Value {
+        let end = self
+            .last
+            .saturating_duration_since(self.start)
+            .as_secs_f64();
+        let width = self.interval.as_secs_f64();
+        let mut points: Vec<(f64, f64, u64)> = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(i, &bytes)| {
+                let from = i as f64 * width;
+                (from, end.min(from + width).max(from), bytes)
+            })
+            .collect();
+        if let [.., previous, (from, to, bytes)] = points[..]
+            && to - from < width * MIN_TAIL
+        {
+            points.pop();
+            *points.last_mut().expect("previous bucket") = (previous.0, to, previous.2 + bytes);
+        }
+        let points: Vec<_> = points
+            .into_iter()
+            .map(|(from, to, bytes)| {
+                Object::new()
+                    .field("secs", &to)
+                    .field("duration_secs", &(to - from))
+                    .field("bytes", &bytes)
+                    .field("mib_per_sec", &(per_sec(bytes, to - from) / MIB))
+            })
+            .collect();
+        points.to_json()
+    }
+}
+
+/// Result of checking the body, see [`bench_payload::Verifier`].
+pub struct Integrity {
+    /// `None` when only the checksum was computed, not the content checked.
+    pub verdict: Option<Verdict>,
+    pub checksum: u64,
+    pub server_checksum: Option<u64>,
+    pub intact: bool,
+}
+
+impl ToJson for Integrity {
+    fn to_json(&self) -> Value {
+        Object::new()
+            .field("intact", &self.intact)
+            .field("content_checked", &self.verdict.is_some())
+            .field(
+                "first_mismatch",
+                &self.verdict.and_then(|v| v.first_mismatch),
+            )
+            .field("checksum", &bench_payload::format_checksum(self.checksum))
+            .field(
+                "server_checksum",
+                &self.server_checksum.map(bench_payload::format_checksum),
+            )
+            .into()
+    }
+}
+
+/// The steady-state part of a timed download.
+pub struct Window {
+    /// Left out from the start of the body.
+    pub warmup: Duration,
+    /// Shorter than `--duration` if the body ended first.
+    pub measured: Duration,
+    pub bytes: u64,
+}
+
+impl ToJson for Window {
+    fn to_json(&self) -> Value {
+        Object::new()
+            .field("warmup_secs", &self.warmup.as_secs_f64())
+            .field("window_secs", &self.measured.as_secs_f64())
+            .field("bytes", &self.bytes)
+            .into()
+    }
+}
+
+/// Timings and throughput of one download.
+pub struct Transfer {
+    pub url: String,
+    pub proxy: Option<String>,
+    pub proxy_mode: Option<&'static str>,
+    pub status: u16,
+    /// TCP connect, after name resolution.
+    pub connect: Duration,
+    /// Proxy handshake, i.e. the SOCKS5 exchange or HTTP `CONNECT`. A
+    /// forwarding HTTP proxy has none.
+    pub handshake: Option<Duration>,
+    /// From the request being written until the response head arrived.
+    pub ttfb: Duration,
+    /// From the response head until the last body byte.
+    pub transfer: Duration,
+    pub bytes: u64,
+    /// Set for a timed download, whose throughput is measured over it.
+    pub window: Option<Window>,
+    pub timeline: Timeline,
+    pub integrity: Option<Integrity>,
+}
+
+impl Transfer {
+    pub fn bytes_per_sec(&self) -> f64 {
+        match &self.window {
+            Some(window) => per_sec(window.bytes, window.measured.as_secs_f64()),
+            None => per_sec(self.bytes, self.transfer.as_secs_f64()),
+        }
+    }
+
+    /// `false` if the body was checked and did not arrive intact.
+    pub fn is_intact(&self) -> bool {
+        self.integrity.as_ref().is_none_or(|i| i.intact)
+    }
+}
+
+impl ToJson for Transfer {
+    fn to_json(&self) -> Value {
+        let handshake = self.handshake.unwrap_or_default();
+        let total = self.connect + handshake + self.ttfb + self.transfer;
+        Object::new()
+            .field("url", &self.url)
+            .field("proxy", &self.proxy)
+            .field("proxy_mode", &self.proxy_mode)
+            .field("status", &self.status)
+            .field("bytes", &self.bytes)
+            .field("connect_secs", &self.connect.as_secs_f64())
+            .field("handshake_secs", &self.handshake.map(|d| d.as_secs_f64()))
+            .field("ttfb_secs", &self.ttfb.as_secs_f64())
+            .field("transfer_secs", &self.transfer.as_secs_f64())
+            .field("total_secs", &total.as_secs_f64())
+            .field("bytes_per_sec", &(self.bytes_per_sec() as u64))
+            .field("mib_per_sec", &(self.bytes_per_sec() / MIB))
+            .field("window", &self.window)
+            .field("timeline", &self.timeline)
+            .field("integrity", &self.integrity)
+            .into()
+    }
+}
+
+/// Bytes per second, or zero for an empty interval.
+fn per_sec(bytes: u64, secs: f64) -> f64 {
+    if secs > 0.0 { bytes as f64 / secs } else { 0.0 }
+}
+
+/// Several downloads that ran at the same time.
+pub struct Aggregate {
+    pub threads: usize,
+    /// From starting the first connection until the last byte arrived.
+    pub wall: Duration,
+    pub timeline: Timeline,
+    pub streams: Vec<Transfer>,
+}
+
+impl Aggregate {
+    pub fn bytes(&self) -> u64 {
+        self.streams.iter().map(|s| s.bytes).sum()
+    }
+
+    /// Over the wall time, or for timed downloads the sum of the streams'
+    /// steady-state rates, whose windows line up.
+    pub fn bytes_per_sec(&self) -> f64 {
+        if self.streams.iter().all(|s| s.window.is_some()) {
+            self.streams.iter().map(Transfer::bytes_per_sec).sum()
+        } else {
+            per_sec(self.bytes(), self.wall.as_secs_f64())
+        }
+    }
+
+    /// Jain's fairness index of the per-stream throughput: 1 when every
+    /// stream got the same share, down to 1/n when one stream got it all.
+    pub fn fairness(&self) -> f64 {
+        let rates: Vec<_> = self.streams.iter().map(Transfer::bytes_per_sec).collect();
+        let sum: f64 = rates.iter().sum();
+        let sum_sq: f64 = rates.iter().map(|r| r * r).sum();
+        if sum_sq > 0.0 {
+            sum * sum / (rates.len() as f64 * sum_sq)
+        } else {
+            0.0
+        }
+    }
+
+    pub fn is_intact(&self) -> bool {
+        self.streams.iter().all(Transfer::is_intact)
+    }
+}
+
+impl ToJson for Aggregate {
+    fn to_json(&self) -> Value {
+        let bytes_per_sec = self.bytes_per_sec();
+        let stream_rates: Vec<_> = self
+            .streams
+            .iter()
+            .map(|s| s.bytes_per_sec() / MIB)
+            .collect();
+        let min = stream_rates.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = stream_rates.iter().copied().fold(0.0, f64::max);
+        Object::new()
+            .field("connections", &self.streams.len())
+            .field("threads", &self.threads)
+            .field("bytes", &self.bytes())
+            .field("wall_secs", &self.wall.as_secs_f64())
+            .field("bytes_per_sec", &(bytes_per_sec as u64))
+            .field("mib_per_sec", &(bytes_per_sec / MIB))
+            .field("stream_mib_per_sec_min", &min)
+            .field("stream_mib_per_sec_max", &max)
+            .field("jain_fairness", &self.fairness())
+            .field("intact", &self.is_intact())
+            .field("timeline", &self.timeline)
+            .field("streams", &self.streams)
+            .into()
+    }
+}
+
+/// Request/response round trips over one connection.
+pub struct Latency {
+    pub url: String,
+    pub proxy: Option<String>,
+    pub proxy_mode: Option<&'static str>,
+    pub connect: Duration,
+    pub handshake: Option<Duration>,
+    /// From the first request being written until the last response ended.
+    pub elapsed: Duration,
+    pub histogram: Histogram,
+}
+
+impl ToJson for Latency {
+    fn to_json(&self) -> Value {
+        let requests = self.histogram.count();
+        Object::new()
+            .field("url", &self.url)
+            .field("proxy", &self.proxy)
+            .field("proxy_mode", &self.proxy_mode)
+            .field("connect_secs", &self.connect.as_secs_f64())
+            .field("handshake_secs", &self.handshake.map(|d| d.as_secs_f64()))
+            .field("requests", &requests)
+            .field("elapsed_secs", &self.elapsed.as_secs_f64())
+            .field(
+                "requests_per_sec",
+                &per_sec(requests, self.elapsed.as_secs_f64()),
+            )
+            .field("latency", &self.histogram)
+            .into()
+    }
+}
+
+/// Small requests over several keep-alive connections at once.
+pub struct Rps {
+    pub url: String,
+    pub proxy: Option<String>,
+    pub proxy_mode: Option<&'static str>,
+    pub connections: usize,
+    pub threads: usize,
+    pub warmup: Duration,
+    /// The measured part of the run, after the warm-up.
+    pub window: Duration,
+    /// Requests that completed within the window.
+    pub latency: Histogram,
+}
+
+impl ToJson for Rps {
+    fn to_json(&self) -> Value {
+        let requests = self.latency.count();
+        Object::new()
+            .field("url", &self.url)
+            .field("proxy", &self.proxy)
+            .field("proxy_mode", &self.proxy_mode)
+            .field("connections", &self.connections)
+            .field("threads", &self.threads)
+            .field("warmup_secs", &self.warmup.as_secs_f64())
+            .field("window_secs", &self.window.as_secs_f64())
+            .field("requests", &requests)
+            .field(
+                "requests_per_sec",
+                &per_sec(requests, self.window.as_secs_f64()),
+            )
+            .field("latency", &self.latency)
+            .into()
+    }
+}
+
+/// Fresh connections opened one after the other, one request each.
+pub struct Churn {
+    pub url: String,
+    pub proxy: Option<String>,
+    pub proxy_mode: Option<&'static str>,
+    /// From the first connect until the last connection was closed.
+    pub elapsed: Duration,
+    /// TCP connect, after name resolution.
+    pub connect: Histogram,
+    /// SOCKS5 or `CONNECT` exchange, if the proxy has one.
+    pub handshake: Option<Histogram>,
+    /// From the request being written until the response head arrived.
+    pub ttfb: Histogram,
+    /// From starting the connect until the response body ended.
+    pub total: Histogram,
+}
+
+impl ToJson for Churn {
+    fn to_json(&self) -> Value {
+        let connections = self.total.count();
+        Object::new()
+            .field("url", &self.url)
+            .field("proxy", &self.proxy)
+            .field("proxy_mode", &self.proxy_mode)
+            .field("connections", &connections)
+            .field("elapsed_secs", &self.elapsed.as_secs_f64())
+            .field(
+                "connections_per_sec",
+                &per_sec(connections, self.elapsed.as_secs_f64()),
+            )
+            .field("connect", &self.connect)
+            .field("handshake", &self.handshake)
+            .field("ttfb", &self.ttfb)
+            .field("total", &self.total)
+            .into()
+    }
+}
+
+/// One run of the workload, over one connection or several.
+pub enum Run {
+    Single(Transfer),
+    Parallel(Aggregate),
+}
+
+impl Run {
+    pub fn bytes_per_sec(&self) -> f64 {
+        match self {
+            Run::Single(transfer) => transfer.bytes_per_sec(),
+            Run::Parallel(aggregate) => aggregate.bytes_per_sec(),
+        }
+    }
+
+    pub fn is_intact(&self) -> bool {
+        match self {
+            Run::Single(transfer) => transfer.is_intact(),
+            Run::Parallel(aggregate) => aggregate.is_intact(),
+        }
+    }
+}
+
+impl ToJson for Run {
+    fn to_json(&self) -> Value {
+        match self {
+            Run::Single(transfer) => transfer.to_json(),
+            Run::Parallel(aggregate) => aggregate.to_json(),
+        }
+    }
+}
+
+/// The same run repeated, with a summary of its throughput.
+pub struct Repeated {
+    pub runs: Vec<Run>,
+    /// Coefficient of variation above which the result is flagged unstable.
+    pub max_cv: f64,
+}
+
+impl Repeated {
+    /// Summary of `mib_per_sec` over the runs.
+    pub fn summary(&self) -> Summary {
+        let samples: Vec<_> = self.runs.iter().map(|r| r.bytes_per_sec() / MIB).collect();
+        Summary::of(&samples).expect("at least one run")
+    }
+
+    /// Whether the runs agree closely enough for the mean to be trusted.
+    pub fn is_stable(&self) -> bool {
+        self.summary().cv() <= self.max_cv
+    }
+
+    pub fn is_intact(&self) -> bool {
+        self.runs.iter().all(Run::is_intact)
+    }
+}
+
+impl ToJson for Repeated {
+    fn to_json(&self) -> Value {
+        Object::new()
+            .field("repeat", &self.runs.len())
+            .field("mib_per_sec", &self.summary())
+            .field("max_cv", &self.max_cv)
+            .field("stable", &self.is_stable())
+            .field("intact", &self.is_intact())
+            .field("runs", &self.runs)
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(secs, duration_secs, bytes)` of each timeline entry.
+    fn points(timeline: &Timeline) -> Vec<(f64, f64, f64)> {
+        let Value::Array(points) = timeline.to_json() else {
+            panic!("timeline is not an array");
+        };
+        points
+            .iter()
+            .map(|p| {
+                let field = |name| p.get(name).and_then(Value::as_f64).unwrap();
+                (field("secs"), field("duration_secs"), field("bytes"))
+            })
+            .collect()
+    }
+
+    fn timeline(records: &[(u64, u64)]) -> Timeline {
+        let start = Instant::now();
+        let mut timeline = Timeline::new(start, Duration::from_secs(1));
+        for &(ms, bytes) in records {
+            timeline.record(start + Duration::from_millis(ms), bytes);
+        }
+        timeline
+    }
+
+    fn aggregate(bytes: &[u64]) -> Aggregate {
+        let start = Instant::now();
+        let interval = Duration::from_secs(1);
+        let streams = bytes
+            .iter()
+            .map(|&bytes| Transfer {
+                url: "http://127.0.0.1/bench".into(),
+                proxy: None,
+                proxy_mode: None,
+                status: 200,
+                connect: Duration::ZERO,
+                handshake: None,
+                ttfb: Duration::ZERO,
+                transfer: Duration::from_secs(2),
+                bytes,
+                window: None,
+                timeline: Timeline::new(start, interval),
+                integrity: None,
+            })
+            .collect();
+        Aggregate {
+            threads: 1,
+            wall: Duration::from_secs(2),
+            timeline: Timeline::new(start, interval),
+            streams,
+        }
+    }
+
+    #[test]
+    fn fairness() {
+        assert_eq!(aggregate(&[100; 4]).fairness(), 1.0);
+        assert_eq!(aggregate(&[100, 0, 0, 0]).fairness(), 0.25);
+        // (1 + 3)² / (2 · (1 + 9))
+        assert_eq!(aggregate(&[100, 300]).fairness(), 0.8);
+        assert_eq!(aggregate(&[0, 0]).fairness(), 0.0);
+        assert_eq!(aggregate(&[]).fairness(), 0.0);
+    }
+
+    #[test]
+    fn timeline_folds_short_tail() {
+        // 100 ms past the last full second is folded into it.
+        let folded = timeline(&[(500, 100), (1500, 200), (2100, 50)]);
+        assert_eq!(points(&folded), [(1.0, 1.0, 100.0), (2.1, 1.1, 250.0)]);
+
+        // 500 ms is long enough to stand on its own.
+        let kept = timeline(&[(500, 100), (1500, 200), (2500, 50)]);
+        assert_eq!(
+            points(&kept),
+            [(1.0, 1.0, 100.0), (2.0, 1.0, 200.0), (2.5, 0.5, 50.0)]
+        );
+
+        // A single short bucket has nothing to fold into.
+        assert_eq!(points(&timeline(&[(100, 10)])), [(0.1, 0.1, 10.0)]);
+        assert!(points(&timeline(&[])).is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::{Barrier, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Context as _;
+use compio::io::AsyncWriteExt as _;
+use futures_util::future::{join_all, try_join_all};
+
+use crate::args::Args;
+use crate::histogram::Histogram;
+use crate::http::Conn;
+use crate::proxy::{self, Proxy};
+use crate::report::Rps;
+
+/// Opens `args.connections` connections, spread round-robin over
+/// `args.threads` compio runtimes like [`crate::parallel`]. Once every one of
+/// them is up, each sends requests one at a time until the warm-up and the
+/// window after it are over.
+pub async fn run(args: &Args) -> anyhow::Result<Rps> {
+    let window = args.duration.context("--rps needs a duration")?;
+    let request = proxy::get(&args.url, args.proxy.as_ref());
+    let threads = args.threads.max(1);
+
+    let results = if threads == 1 {
+        let conns = connect_all(args, args.connections).await?;
+        let start = Instant::now();
+        join_all(
+            conns
+                .into_iter()
+                .map(|conn| hammer(conn, &request, start + args.warmup, window)),
+        )
+        .await
+    } else {
+        let barrier = Barrier::new(threads);
+        let start = OnceLock::new();
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|t| {
+                    let count = (t..args.connections).step_by(threads).count();
+                    let (barrier, start, request) = (&barrier, &start, &request);
+                    scope.spawn(move || {
+                        let runtime = compio::runtime::Runtime::new();
+                        let conns = match &runtime {
+                            Ok(runtime) => runtime.block_on(connect_all(args, count)),
+                            Err(e) => Err(anyhow::anyhow!("compio runtime: {e}")),
+                        };
+                        // Every thread has to get here, even after an error.
+                        barrier.wait();
+                        let (runtime, conns) = (runtime?, conns?);
+                        let start = *start.get_or_init(Instant::now);
+                        let hammers = conns
+                            .into_iter()
+                            .map(|conn| hammer(conn, request, start + args.warmup, window));
+                        anyhow::Ok(runtime.block_on(join_all(hammers)))
+                    })
+                })
+                .collect();
+            let mut results = Vec::new();
+            for worker in workers {
+                let worker = worker
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("client thread panicked"))?;
+                results.extend(worker?);
+            }
+            anyhow::Ok(results)
+        })?
+    };
+
+    let mut latency = Histogram::new();
+    for (i, result) in results.into_iter().enumerate() {
+        latency.merge(&result.with_context(|| format!("connection {i}"))?);
+    }
+    Ok(Rps {
+        url: args.url.to_string(),
+        proxy: args.proxy.as_ref().map(ToString::to_string),
+        proxy_mode: args.proxy.as_ref().map(Proxy::mode),
+        connections: args.connections,
+        threads,
+        warmup: args.warmup,
+        window,
+        latency,
+    })
+}
+
+async fn connect_all(args: &Args, count: usize) -> anyhow::Result<Vec<Conn>> {
+    let connects = (0..count).map(|_| proxy::connect(&args.url, args.proxy.as_ref()));
+    let connected = try_join_all(connects).await?;
+    Ok(connected.into_iter().map(|c| c.conn).collect())
+}
+
+/// Sends `request` over `conn` until `from + window`, recording the latency of
+/// the requests that started after `from` and completed within the window.
+async fn hammer(
+    mut conn: Conn,
+    request: &str,
+    from: Instant,
+    window: Duration,
+) -> anyhow::Result<Histogram> {
+    let end = from + window;
+    let mut latency = Histogram::new();
+    loop {
+        let sent = Instant::now();
+        if sent >= end {
+            break;
+        }
+        conn.stream.write_all(request.to_owned()).await.0?;
+        let resp = conn.read_response().await?;
+        conn.read_body(resp.body()?, &mut |_| {}).await?;
+        let done = Instant::now();
+
+        if resp.status != 200 {
+            anyhow::bail!("{} {}", resp.status, resp.reason);
+        }
+        if !resp.keeps_alive() {
+            anyhow::bail!("server closed a keep-alive connection");
+        }
+        if sent >= from && done < end {
+            latency.record(done - sent);
+        }
+    }
+    _ = conn.stream.close().await;
+    Ok(latency)
+}
@@ -0,0 +1,337 @@
+//! SOCKS5 CONNECT (RFC 1928) with no-auth or username/password (RFC 1929).
+
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{Context as _, bail};
+use compio::{
+    BufResult,
+    buf::IoBuf as _,
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::TcpStream,
+};
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0x00;
+const USER_PASS: u8 = 0x02;
+const NO_ACCEPTABLE: u8 = 0xff;
+const CONNECT: u8 = 0x01;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Where the proxy should connect to.
+pub enum Destination<'a> {
+    Addr(SocketAddr),
+    /// Resolved by the proxy, as with `socks5h://`.
+    Domain(&'a str, u16),
+}
+
+/// Asks the SOCKS5 server on `stream` to connect to `dest`. Once this returns
+/// the stream is a tunnel to `dest`.
+pub async fn handshake(
+    stream: &mut TcpStream,
+    dest: Destination<'_>,
+    auth: Option<(&str, &str)>,
+) -> anyhow::Result<()> {
+    let greeting = match auth {
+        Some(_) => vec![VERSION, 2, NO_AUTH, USER_PASS],
+        None => vec![VERSION, 1, NO_AUTH],
+    };
+    stream.write_all(greeting).await.0?;
+
+    let [version, method] = read_array(stream).await?;
+    if version != VERSION {
+        bail!("not a SOCKS5 server (version {version})");
+    }
+    match (method, auth) {
+        (NO_AUTH, _) => {}
+        (USER_PASS, Some((user, pass))) => authenticate(stream, user, pass).await?,
+        (NO_ACCEPTABLE, None) => bail!("SOCKS5 server requires authentication"),
+        (NO_ACCEPTABLE, Some(_)) => bail!("SOCKS5 server rejected username/password auth"),
+        (method, _) => bail!("SOCKS5 server chose unsupported method {method:#04x}"),
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0];
+    let port = match dest {
+        Destination::Addr(addr) => {
+            match addr.ip() {
+                IpAddr::V4(ip) => {
+                    request.push(ATYP_IPV4);
+                    request.extend_from_slice(&ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    request.push(ATYP_IPV6);
+                    request.extend_from_slice(&ip.octets());
+                }
+            }
+            addr.port()
+        }
+        Destination::Domain(host, port) => {
+            let len = u8::try_from(host.len()).context("host name longer than 255 bytes")?;
+            request.extend_from_slice(&[ATYP_DOMAIN, len]);
+            request.extend_from_slice(host.as_bytes());
+            port
+        }
+    };
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(request).await.0?;
+
+    let [version, reply, _, atyp] = read_array(stream).await?;
+    if version != VERSION {
+        bail!("bad SOCKS5 reply version {version}");
+    }
+    if reply != 0 {
+        bail!("SOCKS5 connect failed: {}", reply_message(reply));
+    }
+    // Skip the bound address and port, which nothing here needs.
+    let addr_len = match atyp {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => usize::from(read_array::<1>(stream).await?[0]),
+        atyp => bail!("bad SOCKS5 address type {atyp}"),
+    };
+    skip(stream, addr_len + 2).await
+}
+
+async fn authenticate(stream: &mut TcpStream, user: &str, pass: &str) -> anyhow::Result<()> {
+    let user_len = u8::try_from(user.len()).context("SOCKS5 username longer than 255 bytes")?;
+    let pass_len = u8::try_from(pass.len()).context("SOCKS5 password longer than 255 bytes")?;
+    let mut request = vec![1, user_len];
+    request.extend_from_slice(user.as_bytes());
+    request.push(pass_len);
+    request.extend_from_slice(pass.as_bytes());
+    stream.write_all(request).await.0?;
+
+    let [_, status] = read_array(stream).await?;
+    if status != 0 {
+        bail!("SOCKS5 username/password rejected");
+    }
+    Ok(())
+}
+
+fn reply_message(reply: u8) -> String {
+    match reply {
+        0x01 => "general failure".into(),
+        0x02 => "connection not allowed by ruleset".into(),
+        0x03 => "network unreachable".into(),
+        0x04 => "host unreachable".into(),
+        0x05 => "connection refused".into(),
+        0x06 => "TTL expired".into(),
+        0x07 => "command not supported".into(),
+        0x08 => "address type not supported".into(),
+        reply => format!("reply {reply:#04x}"),
+    }
+}
+
+async fn read_array<const N: usize>(stream: &mut TcpStream) -> anyhow::Result<[u8; N]> {
+    let BufResult(result, buf) = stream.read_exact([0; N]).await;
+    result.context("SOCKS5 server closed the connection")?;
+    Ok(buf)
+}
+
+async fn skip(stream: &mut TcpStream, len: usize) -> anyhow::Result<()> {
+    let buf = Vec::with_capacity(len).slice(..len);
+    let BufResult(result, _) = stream.read_exact(buf).await;
+    result.context("SOCKS5 server closed the connection")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read as _, Write as _};
+    use std::net::{Ipv4Addr, Ipv6Addr, TcpListener};
+    use std::thread;
+
+    use super::*;
+
+    /// Runs a handshake against a server that, for each `(request, reply)`
+    /// step, reads as many bytes as `request` has and answers with `reply`.
+    /// Returns the result and what the server actually received per step.
+    fn run(
+        dest: Destination<'_>,
+        auth: Option<(&str, &str)>,
+        script: &[(&[u8], &[u8])],
+    ) -> (anyhow::Result<()>, Vec<Vec<u8>>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let script: Vec<_> = script
+            .iter()
+            .map(|(req, reply)| (req.len(), reply.to_vec()))
+            .collect();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            for (len, reply) in script {
+                let mut buf = vec![0; len];
+                if stream.read_exact(&mut buf).is_err() {
+                    break;
+                }
+                received.push(buf);
+                stream.write_all(&reply).unwrap();
+            }
+            received
+        });
+
+        let result = compio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut stream = TcpStream::connect(addr).await?;
+            handshake(&mut stream, dest, auth).await
+        });
+        (result, server.join().unwrap())
+    }
+
+    fn expect_ok(dest: Destination<'_>, auth: Option<(&str, &str)>, script: &[(&[u8], &[u8])]) {
+        let (result, received) = run(dest, auth, script);
+        result.unwrap();
+        let expected: Vec<_> = script.iter().map(|(req, _)| req.to_vec()).collect();
+        assert_eq!(received, expected);
+    }
+
+    fn expect_err(
+        dest: Destination<'_>,
+        auth: Option<(&str, &str)>,
+        script: &[(&[u8], &[u8])],
+        message: &str,
+    ) {
+        let error = run(dest, auth, script).0.unwrap_err().to_string();
+        assert!(error.contains(message), "{error:?} lacks {message:?}");
+    }
+
+    const OK_V4: &[u8] = &[5, 0, 0, ATYP_IPV4, 127, 0, 0, 1, 0x1f, 0x90];
+
+    #[test]
+    fn connect_ipv4_no_auth() {
+        let dest = Destination::Addr((Ipv4Addr::new(10, 0, 0, 1), 8089).into());
+        expect_ok(
+            dest,
+            None,
+            &[
+                (&[5, 1, NO_AUTH], &[5, NO_AUTH]),
+                (&[5, CONNECT, 0, ATYP_IPV4, 10, 0, 0, 1, 0x1f, 0x99], OK_V4),
+            ],
+        );
+    }
+
+    #[test]
+    fn connect_ipv6() {
+        let dest = Destination::Addr((Ipv6Addr::LOCALHOST, 443).into());
+        let mut request = vec![5, CONNECT, 0, ATYP_IPV6];
+        request.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        request.extend_from_slice(&443u16.to_be_bytes());
+        let mut reply = vec![5, 0, 0, ATYP_IPV6];
+        reply.extend_from_slice(&[0; 18]);
+        expect_ok(
+            dest,
+            None,
+            &[(&[5, 1, NO_AUTH], &[5, NO_AUTH]), (&request, &reply)],
+        );
+    }
+
+    #[test]
+    fn connect_domain_with_auth() {
+        let dest = Destination::Domain("example.com", 80);
+        let mut request = vec![5, CONNECT, 0, ATYP_DOMAIN, 11];
+        request.extend_from_slice(b"example.com");
+        request.extend_from_slice(&[0, 80]);
+        // The bound address may be a domain name as well.
+        let reply = [&[5, 0, 0, ATYP_DOMAIN, 4][..], b"host", &[0, 1]].concat();
+        expect_ok(
+            dest,
+            Some(("user", "secret")),
+            &[
+                (&[5, 2, NO_AUTH, USER_PASS], &[5, USER_PASS]),
+                (b"\x01\x04user\x06secret", &[1, 0]),
+                (&request, &reply),
+            ],
+        );
+    }
+
+    #[test]
+    fn server_may_skip_offered_auth() {
+        let dest = Destination::Domain("a", 1);
+        expect_ok(
+            dest,
+            Some(("user", "pass")),
+            &[
+                (&[5, 2, NO_AUTH, USER_PASS], &[5, NO_AUTH]),
+                (&[5, CONNECT, 0, ATYP_DOMAIN, 1, b'a', 0, 1], OK_V4),
+            ],
+        );
+    }
+
+    #[test]
+    fn method_errors() {
+        let domain = || Destination::Domain("a", 1);
+        expect_err(
+            domain(),
+            None,
+            &[(&[5, 1, NO_AUTH], &[4, NO_AUTH])],
+            "not a SOCKS5 server",
+        );
+        expect_err(
+            domain(),
+            None,
+            &[(&[5, 1, NO_AUTH], &[5, NO_ACCEPTABLE])],
+            "requires authentication",
+        );
+        expect_err(
+            domain(),
+            Some(("u", "p")),
+            &[(&[5, 2, NO_AUTH, USER_PASS], &[5, NO_ACCEPTABLE])],
+            "rejected username/password",
+        );
+        // Username/password was not offered.
+        expect_err(
+            domain(),
+            None,
+            &[(&[5, 1, NO_AUTH], &[5, USER_PASS])],
+            "unsupported method 0x02",
+        );
+        expect_err(
+            domain(),
+            Some(("u", "p")),
+            &[
+                (&[5, 2, NO_AUTH, USER_PASS], &[5, USER_PASS]),
+                (b"\x01\x01u\x01p", &[1, 1]),
+            ],
+            "username/password rejected",
+        );
+    }
+
+    #[test]
+    fn reply_errors() {
+        let connect: &[u8] = &[5, CONNECT, 0, ATYP_DOMAIN, 1, b'a', 0, 1];
+        let greeting = (&[5, 1, NO_AUTH][..], &[5, NO_AUTH][..]);
+        for (reply, message) in [
+            (&[5, 5, 0, ATYP_IPV4][..], "connection refused"),
+            (&[5, 0x2a, 0, ATYP_IPV4], "reply 0x2a"),
+            (&[4, 0, 0, ATYP_IPV4], "bad SOCKS5 reply version"),
+            (&[5, 0, 0, 9], "bad SOCKS5 address type 9"),
+            // The bound address is cut short.
+            (&[5, 0, 0, ATYP_IPV4, 127, 0], "closed the connection"),
+        ] {
+            expect_err(
+                Destination::Domain("a", 1),
+                None,
+                &[greeting, (connect, reply)],
+                message,
+            );
+        }
+    }
+
+    #[test]
+    fn long_names() {
+        let long = "x".repeat(256);
+        expect_err(
+            Destination::Domain(&long, 1),
+            None,
+            &[(&[5, 1, NO_AUTH], &[5, NO_AUTH])],
+            "host name longer than 255 bytes",
+        );
+        expect_err(
+            Destination::Domain("a", 1),
+            Some((&long, "p")),
+            &[(&[5, 2, NO_AUTH, USER_PASS], &[5, USER_PASS])],
+            "username longer than 255 bytes",
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Checks a body read from stdin against the content fast-server was asked
+//! for, e.g.
+//!
+//! ```text
+//! curl -s 'http://127.0.0.1:8089/bench?size=1073741824&content=random&seed=7' \
+//!     | verify-payload --content random --seed 7 --size 1073741824
+//! ```
+//!
+//! Prints one JSON line and exits with status 1 if the body is not intact.
+
+use std::io::{self, Read as _};
+use std::process::ExitCode;
+
+use bench_payload::{Content, Verifier};
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("verify-payload: {e}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run() -> Result<bool, String> {
+    let mut content = Content::Random(bench_payload::DEFAULT_SEED);
+    let mut size = None;
+    let mut checksum = None;
+    let mut file = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--content" => {
+                let name = value()?;
+                content = Content::parse(&name).ok_or(format!("unknown content {name:?}"))?;
+            }
+            "--seed" => {
+                let seed = value()?;
+                content = Content::Random(seed.parse().map_err(|_| format!("bad seed {seed:?}"))?);
+            }
+            "--size" => {
+                let n = value()?;
+                size = Some(bench_payload::parse_size(&n).ok_or(format!("bad size {n:?}"))?);
+            }
+            "--checksum" => {
+                let sum = value()?;
+                checksum = Some(
+                    bench_payload::parse_checksum(&sum)
+                        .ok_or(format!("bad checksum {sum:?}, expected xxh64=<hex>"))?,
+                );
+            }
+            "--file" => file = Some(value()?),
+            _ => return Err(format!("unknown argument {arg:?}")),
+        }
+    }
+
+    let block = match (content.block(), file) {
+        (Some(block), _) => block,
+        (None, Some(path)) => std::fs::read(&path).map_err(|e| format!("{path}: {e}"))?,
+        (None, None) => return Err("--content file needs --file PATH".into()),
+    };
+    if block.is_empty() {
+        return Err("payload file is empty".into());
+    }
+
+    let mut verifier = Verifier::new(block);
+    let mut buf = vec![0; bench_payload::BLOCK_SIZE];
+    let mut stdin = io::stdin().lock();
+    loop {
+        match stdin.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => verifier.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+
+    let verdict = verifier.finish();
+    let intact = verdict.is_intact(size.unwrap_or(verdict.received), checksum);
+    let first_mismatch = verdict
+        .first_mismatch
+        .map_or("null".to_string(), |offset| offset.to_string());
+    println!(
+        "{{\"received\":{},\"intact\":{intact},\"first_mismatch\":{first_mismatch},\"checksum\":\"{}\"}}",
+        verdict.received,
+        bench_payload::format_checksum(verdict.checksum),
+    );
+    Ok(intact)
+}
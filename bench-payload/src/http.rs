@@ -0,0 +1,155 @@
+//! Reading HTTP/1 heads and lines out of a read-ahead buffer, shared by
+//! fast-server's requests and bench-client's responses.
+
+use std::io;
+
+use compio::{
+    BufResult,
+    io::{AsyncRead, AsyncReadExt as _},
+};
+
+/// Upper bound for the start line plus headers of a single head, and for any
+/// other line read with [`read_line`].
+pub const MAX_HEAD_SIZE: usize = 64 * 1024;
+/// Room reserved for each read into the buffer.
+pub const READ_SIZE: usize = 8 * 1024;
+
+/// Why [`read_line`] failed.
+#[derive(Debug)]
+pub enum LineError {
+    Io(io::Error),
+    /// No line end within [`MAX_HEAD_SIZE`] bytes.
+    TooLong,
+    NotUtf8,
+    /// The stream ended before the line did.
+    Closed,
+}
+
+/// Offset just past the empty line terminating the head, accepting bare LF.
+pub fn find_head_end(buf: &[u8]) -> Option<usize> {
+    let mut line_start = 0;
+    for (i, &b) in buf.iter().enumerate() {
+        if b == b'\n' {
+            let line = &buf[line_start..i];
+            if line.is_empty() || line == b"\r" {
+                return Some(i + 1);
+            }
+            line_start = i + 1;
+        }
+    }
+    None
+}
+
+/// Takes the next line off the front of `buf` without its CRLF or bare LF,
+/// reading from `stream` until there is one.
+pub async fn read_line(
+    stream: &mut impl AsyncRead,
+    buf: &mut Vec<u8>,
+) -> Result<String, LineError> {
+    loop {
+        if let Some(end) = buf.iter().position(|&b| b == b'\n') {
+            let line = buf[..end].strip_suffix(b"\r").unwrap_or(&buf[..end]);
+            let line = String::from_utf8(line.to_vec()).map_err(|_| LineError::NotUtf8)?;
+            buf.drain(..=end);
+            return Ok(line);
+        }
+        if buf.len() >= MAX_HEAD_SIZE {
+            return Err(LineError::TooLong);
+        }
+        if fill(stream, buf).await.map_err(LineError::Io)? == 0 {
+            return Err(LineError::Closed);
+        }
+    }
+}
+
+/// Appends one read from `stream` to `buf`. Returns 0 at end of stream.
+pub async fn fill(stream: &mut impl AsyncRead, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let mut owned = std::mem::take(buf);
+    owned.reserve(READ_SIZE);
+    let BufResult(result, owned) = stream.append(owned).await;
+    *buf = owned;
+    result
+}
+
+/// Decodes `%XX` escapes. A `%` not followed by two hex digits is kept as
+/// is, and bytes that do not form UTF-8 are replaced.
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes[i] {
+            b'%' if i + 2 < bytes.len() => std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|h| u8::from_str_radix(h, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn head_end() {
+        assert_eq!(
+            find_head_end(b"GET / HTTP/1.1\r\nHost: a\r\n\r\nbody"),
+            Some(27)
+        );
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\nHost: a\n\nbody"), Some(24));
+        assert_eq!(find_head_end(b"\r\n"), Some(2));
+        assert_eq!(find_head_end(b"GET / HTTP/1.1\r\nHost: a\r\n"), None);
+        assert_eq!(find_head_end(b""), None);
+    }
+
+    #[test]
+    fn percent() {
+        assert_eq!(percent_decode("p%40ss"), "p@ss");
+        assert_eq!(percent_decode("%41%62c"), "Abc");
+        assert_eq!(percent_decode("1+2"), "1+2");
+        assert_eq!(percent_decode("%zz"), "%zz");
+        assert_eq!(percent_decode("a%4"), "a%4");
+        assert_eq!(percent_decode("a%"), "a%");
+        assert_eq!(percent_decode("%e4%b8%ad"), "中");
+        assert_eq!(percent_decode("%ff"), "\u{fffd}");
+    }
+
+    #[test]
+    fn lines() {
+        compio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut stream: &[u8] = b"\n5\r\nhello\nlast";
+            let mut buf = b"a;ext=1\r".to_vec();
+            assert_eq!(read_line(&mut stream, &mut buf).await.unwrap(), "a;ext=1");
+            assert_eq!(read_line(&mut stream, &mut buf).await.unwrap(), "5");
+            assert_eq!(read_line(&mut stream, &mut buf).await.unwrap(), "hello");
+            assert!(matches!(
+                read_line(&mut stream, &mut buf).await,
+                Err(LineError::Closed)
+            ));
+            assert_eq!(buf, b"last");
+
+            let mut buf = b"\xff\n".to_vec();
+            assert!(matches!(
+                read_line(&mut stream, &mut buf).await,
+                Err(LineError::NotUtf8)
+            ));
+            let mut buf = vec![b'x'; MAX_HEAD_SIZE];
+            assert!(matches!(
+                read_line(&mut stream, &mut buf).await,
+                Err(LineError::TooLong)
+            ));
+        });
+    }
+}
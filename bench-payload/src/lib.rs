@@ -0,0 +1,315 @@
+//! Body content shared by fast-server and the clients that check it.
+//!
+//! Every body is a block repeated end to end: byte `i` of a body is byte
+//! `i % block.len()` of the block. Knowing the content and seed is therefore
+//! enough to check every received byte, and [`Verifier`] does that while also
+//! hashing the stream so it can be compared with the server's checksum trailer.
+
+pub mod base64;
+pub mod http;
+pub mod json;
+pub mod stats;
+mod xxh64;
+
+use std::fmt;
+use std::time::Duration;
+
+pub use xxh64::Xxh64;
+
+/// Length of the generated blocks.
+pub const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Seed of `content=random` when the request does not pass one.
+pub const DEFAULT_SEED: u64 = 0x5eed;
+
+/// Trailer fast-server sends the body's checksum in when asked for one.
+pub const CHECKSUM_TRAILER: &str = "X-Payload-Checksum";
+
+/// What the body bytes are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Content {
+    Zeros,
+    /// Incompressible bytes from a PRNG with the given seed.
+    Random(u64),
+    /// Byte `i` is `i as u8`.
+    Pattern,
+    /// The server's `PAYLOAD_FILE`, repeated.
+    File,
+}
+
+impl Content {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "zeros" | "zero" => Some(Content::Zeros),
+            "random" => Some(Content::Random(DEFAULT_SEED)),
+            "pattern" => Some(Content::Pattern),
+            "file" | "corpus" => Some(Content::File),
+            _ => None,
+        }
+    }
+
+    /// The block this content repeats, or `None` for [`Content::File`] whose
+    /// bytes only the server has.
+    pub fn block(self) -> Option<Vec<u8>> {
+        match self {
+            Content::Zeros => Some(vec![0; BLOCK_SIZE]),
+            Content::Random(seed) => Some(random_block(seed)),
+            Content::Pattern => Some((0..BLOCK_SIZE).map(|i| i as u8).collect()),
+            Content::File => None,
+        }
+    }
+}
+
+/// Formats as the `content=` (and `seed=`) query parameters.
+impl fmt::Display for Content {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Content::Zeros => f.write_str("content=zeros"),
+            Content::Random(seed) => write!(f, "content=random&seed={seed}"),
+            Content::Pattern => f.write_str("content=pattern"),
+            Content::File => f.write_str("content=file"),
+        }
+    }
+}
+
+/// splitmix64 output, little endian.
+fn random_block(seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..BLOCK_SIZE / 8)
+        .flat_map(|_| {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            (z ^ (z >> 31)).to_le_bytes()
+        })
+        .collect()
+}
+
+/// XXH64 of a `len` byte body repeating `block`, as sent in the
+/// [`CHECKSUM_TRAILER`].
+pub fn checksum(block: &[u8], len: u64) -> u64 {
+    let mut hasher = Xxh64::new(0);
+    let mut left = len;
+    while left > 0 {
+        let n = left.min(block.len() as u64) as usize;
+        hasher.update(&block[..n]);
+        left -= n as u64;
+    }
+    hasher.finish()
+}
+
+/// Checks a body as it arrives, in pieces of any size.
+pub struct Verifier {
+    block: Vec<u8>,
+    hasher: Xxh64,
+    received: u64,
+    first_mismatch: Option<u64>,
+}
+
+impl Verifier {
+    pub fn new(block: Vec<u8>) -> Self {
+        assert!(!block.is_empty(), "cannot verify against an empty block");
+        Self {
+            block,
+            hasher: Xxh64::new(0),
+            received: 0,
+            first_mismatch: None,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.hasher.update(data);
+        while !data.is_empty() {
+            let offset = (self.received % self.block.len() as u64) as usize;
+            let n = data.len().min(self.block.len() - offset);
+            let (piece, rest) = data.split_at(n);
+            if self.first_mismatch.is_none() {
+                let expected = &self.block[offset..offset + n];
+                if let Some(i) = piece.iter().zip(expected).position(|(a, b)| a != b) {
+                    self.first_mismatch = Some(self.received + i as u64);
+                }
+            }
+            self.received += n as u64;
+            data = rest;
+        }
+    }
+
+    pub fn finish(self) -> Verdict {
+        Verdict {
+            received: self.received,
+            first_mismatch: self.first_mismatch,
+            checksum: self.hasher.finish(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Verdict {
+    pub received: u64,
+    /// Body offset of the first byte that differs from the expected content.
+    pub first_mismatch: Option<u64>,
+    /// XXH64 of everything received.
+    pub checksum: u64,
+}
+
+impl Verdict {
+    /// Whether exactly `len` expected bytes arrived and, if the server sent a
+    /// checksum, whether it matches what was received.
+    pub fn is_intact(&self, len: u64, server_checksum: Option<u64>) -> bool {
+        self.received == len
+            && self.first_mismatch.is_none()
+            && server_checksum.is_none_or(|sum| sum == self.checksum)
+    }
+}
+
+/// Parses a byte count such as `1048576`, `512KiB`, `4 GiB`, `1.5GB` or
+/// `2.86 MiB`, i.e. the suffixes `humansize` prints in BINARY and DECIMAL
+/// style. Units are case-insensitive.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => return None,
+    };
+
+    if let Ok(n) = number.parse::<u64>() {
+        return n.checked_mul(multiplier);
+    }
+    let n: f64 = number.parse().ok()?;
+    let bytes = (n * multiplier as f64).round();
+    (bytes.is_finite() && bytes < u64::MAX as f64).then_some(bytes as u64)
+}
+
+/// Parses a duration such as `30`, `30s`, `1.5s`, `500ms` or `2m`. A bare
+/// number is seconds.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let secs: f64 = number.parse().ok()?;
+    let secs = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "s" | "sec" | "secs" => secs,
+        "ms" => secs / 1000.0,
+        "m" | "min" => secs * 60.0,
+        "h" => secs * 3600.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// Parses a checksum as formatted in the trailer, `xxh64=<16 hex digits>`.
+pub fn parse_checksum(value: &str) -> Option<u64> {
+    let hex = value.trim().strip_prefix("xxh64=")?;
+    u64::from_str_radix(hex, 16).ok()
+}
+
+/// Formats a checksum for the trailer.
+pub fn format_checksum(sum: u64) -> String {
+    format!("xxh64={sum:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_trailer() {
+        assert_eq!(
+            format_checksum(0xef46_db37_51d8_e999),
+            "xxh64=ef46db3751d8e999"
+        );
+        assert_eq!(format_checksum(1), "xxh64=0000000000000001");
+        assert_eq!(
+            parse_checksum(" xxh64=ef46db3751d8e999 "),
+            Some(0xef46_db37_51d8_e999)
+        );
+        for value in [
+            "",
+            "xxh64=",
+            "ef46db3751d8e999",
+            "crc32=1",
+            "xxh64=xyz",
+            "xxh64=1ffffffffffffffff",
+        ] {
+            assert_eq!(parse_checksum(value), None, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1048576"), Some(1 << 20));
+        assert_eq!(parse_size("512KiB"), Some(512 << 10));
+        assert_eq!(parse_size(" 4 gib "), Some(4 << 30));
+        assert_eq!(parse_size("1.5GB"), Some(1_500_000_000));
+        assert_eq!(parse_size(".5KiB"), Some(512));
+        assert_eq!(parse_size("2.86 MiB"), Some(2_998_927));
+        assert_eq!(parse_size("18446744073709551615"), Some(u64::MAX));
+        for s in [
+            "",
+            "KiB",
+            ".",
+            "1.2.3",
+            "-1",
+            "4XB",
+            "4 EiB",
+            "18446744073709551616",
+            "16777216TiB",
+            "1e30",
+        ] {
+            assert_eq!(parse_size(s), None, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration(" 1.5 S "), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("0s"), Some(Duration::ZERO));
+        for s in ["", "s", "5x", "5 days", "-1s", "1e3s", "1.2.3s", "1e400"] {
+            assert_eq!(parse_duration(s), None, "{s:?}");
+        }
+        let huge = format!("{}h", u64::MAX);
+        assert_eq!(parse_duration(&huge), None);
+    }
+
+    #[test]
+    fn verdict() {
+        let block = Content::Pattern.block().unwrap();
+        let mut verifier = Verifier::new(block.clone());
+        verifier.update(&block[..10]);
+        verifier.update(&[0; 5]);
+        let verdict = verifier.finish();
+        assert_eq!(verdict.received, 15);
+        assert_eq!(verdict.first_mismatch, Some(10));
+        assert!(!verdict.is_intact(15, None));
+
+        let mut verifier = Verifier::new(block.clone());
+        verifier.update(&block[..100]);
+        let verdict = verifier.finish();
+        let sum = checksum(&block, 100);
+        assert_eq!(verdict.checksum, sum);
+        assert!(verdict.is_intact(100, Some(sum)));
+        assert!(!verdict.is_intact(100, Some(sum ^ 1)));
+        assert!(!verdict.is_intact(101, None));
+    }
+}
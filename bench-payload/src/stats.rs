@@ -0,0 +1,177 @@
+//! Summary statistics over repeated runs.
+
+use crate::json::{Object, ToJson, Value};
+
+/// Mean, spread and a 95% confidence interval of the mean.
+pub struct Summary {
+    pub n: usize,
+    pub mean: f64,
+    pub median: f64,
+    /// Sample standard deviation, zero for a single sample.
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+    /// Student's t interval, `None` for a single sample.
+    pub ci95: Option<(f64, f64)>,
+}
+
+impl Summary {
+    /// `None` if there are no samples.
+    pub fn of(samples: &[f64]) -> Option<Self> {
+        let n = samples.len();
+        if n == 0 {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let mean = sorted.iter().sum::<f64>() / n as f64;
+        let median = if n % 2 == 1 {
+            sorted[n / 2]
+        } else {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+        };
+        let stddev = if n > 1 {
+            let ss: f64 = sorted.iter().map(|x| (x - mean) * (x - mean)).sum();
+            (ss / (n - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+        let ci95 = (n > 1).then(|| {
+            let half = t_975(n - 1) * stddev / (n as f64).sqrt();
+            (mean - half, mean + half)
+        });
+        Some(Self {
+            n,
+            mean,
+            median,
+            stddev,
+            min: sorted[0],
+            max: sorted[n - 1],
+            ci95,
+        })
+    }
+
+    /// Coefficient of variation, the standard deviation relative to the mean.
+    pub fn cv(&self) -> f64 {
+        if self.mean > 0.0 {
+            self.stddev / self.mean
+        } else {
+            0.0
+        }
+    }
+}
+
+impl ToJson for Summary {
+    fn to_json(&self) -> Value {
+        Object::new()
+            .field("n", &self.n)
+            .field("mean", &self.mean)
+            .field("median", &self.median)
+            .field("stddev", &self.stddev)
+            .field("min", &self.min)
+            .field("max", &self.max)
+            .field("ci95_low", &self.ci95.map(|(low, _)| low))
+            .field("ci95_high", &self.ci95.map(|(_, high)| high))
+            .field("cv", &self.cv())
+            .into()
+    }
+}
+
+/// Two-sided 95% quantile of Student's t distribution with `df` degrees of
+/// freedom. Exact up to 30, then interpolated linearly in `1 / df` between
+/// the values for 30, 40, 60, 120 and the normal distribution's 1.960.
+fn t_975(df: usize) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+    /// `(df, t)`, with `df = 0` standing for infinity.
+    const TAIL: [(usize, f64); 5] = [
+        (30, 2.042),
+        (40, 2.021),
+        (60, 2.000),
+        (120, 1.980),
+        (0, 1.960),
+    ];
+
+    let inverse = |df: usize| if df == 0 { 0.0 } else { 1.0 / df as f64 };
+    match df {
+        0 => f64::NAN,
+        1..=30 => TABLE[df - 1],
+        _ => {
+            let x = inverse(df);
+            let (lower, upper) = TAIL
+                .windows(2)
+                .map(|pair| (pair[0], pair[1]))
+                .find(|&(_, (upper, _))| upper == 0 || df <= upper)
+                .expect("the last entry is infinity");
+            let (x0, x1) = (inverse(lower.0), inverse(upper.0));
+            upper.1 + (lower.1 - upper.1) * (x - x1) / (x0 - x1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Summary, t_975};
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn exact_table_values() {
+        assert!(t_975(0).is_nan());
+        assert_eq!(t_975(1), 12.706);
+        assert_eq!(t_975(30), 2.042);
+        assert_eq!(t_975(40), 2.021);
+        assert_eq!(t_975(120), 1.980);
+    }
+
+    #[test]
+    fn interpolates_between_table_values() {
+        // Reference quantiles: t(35) = 2.030, t(50) = 2.009, t(200) = 1.972.
+        for (df, t) in [(35, 2.030), (50, 2.009), (200, 1.972)] {
+            assert!((t_975(df) - t).abs() < 0.001, "t({df}) = {}", t_975(df));
+        }
+        assert!(t_975(31) < 2.042 && t_975(31) > 2.021);
+        assert!((t_975(1_000_000) - 1.960).abs() < 0.0001);
+    }
+
+    #[test]
+    fn summary_of_odd_samples() {
+        let s = Summary::of(&[3.0, 1.0, 2.0]).unwrap();
+        assert_eq!(
+            (s.n, s.mean, s.median, s.min, s.max),
+            (3, 2.0, 2.0, 1.0, 3.0)
+        );
+        // Sample standard deviation: divided by n - 1.
+        assert_eq!(s.stddev, 1.0);
+        let (low, high) = s.ci95.unwrap();
+        let half = 4.303 / 3f64.sqrt();
+        assert!(close(low, 2.0 - half) && close(high, 2.0 + half));
+        assert_eq!(s.cv(), 0.5);
+    }
+
+    #[test]
+    fn summary_of_even_samples() {
+        let s = Summary::of(&[9.0, 2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0]).unwrap();
+        assert_eq!((s.mean, s.median), (5.0, 4.5));
+        assert!(close(s.stddev, (32.0f64 / 7.0).sqrt()));
+        let (low, high) = s.ci95.unwrap();
+        let half = 2.365 * s.stddev / 8f64.sqrt();
+        assert!(close(high - s.mean, half) && close(s.mean - low, half));
+    }
+
+    #[test]
+    fn single_sample_is_stable() {
+        assert!(Summary::of(&[]).is_none());
+        let s = Summary::of(&[42.0]).unwrap();
+        assert_eq!((s.mean, s.median, s.stddev), (42.0, 42.0, 0.0));
+        assert!(s.ci95.is_none());
+        // Nothing to vary, so any threshold passes, even zero.
+        assert_eq!(s.cv(), 0.0);
+        assert_eq!(Summary::of(&[0.0, 0.0]).unwrap().cv(), 0.0);
+    }
+}
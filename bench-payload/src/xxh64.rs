@@ -0,0 +1,191 @@
+//! Streaming XXH64, following the reference implementation.
+
+const PRIME1: u64 = 0x9e37_79b1_85eb_ca87;
+const PRIME2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+const PRIME3: u64 = 0x1656_67b1_9e37_79f9;
+const PRIME4: u64 = 0x85eb_ca77_c2b2_ae63;
+const PRIME5: u64 = 0x27d4_eb2f_1656_67c5;
+
+pub struct Xxh64 {
+    seed: u64,
+    acc: [u64; 4],
+    /// Bytes that do not fill a 32 byte stripe yet.
+    pending: [u8; 32],
+    pending_len: usize,
+    total_len: u64,
+}
+
+impl Xxh64 {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            acc: [
+                seed.wrapping_add(PRIME1).wrapping_add(PRIME2),
+                seed.wrapping_add(PRIME2),
+                seed,
+                seed.wrapping_sub(PRIME1),
+            ],
+            pending: [0; 32],
+            pending_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+
+        if self.pending_len > 0 {
+            let n = data.len().min(32 - self.pending_len);
+            self.pending[self.pending_len..self.pending_len + n].copy_from_slice(&data[..n]);
+            self.pending_len += n;
+            data = &data[n..];
+            if self.pending_len < 32 {
+                return;
+            }
+            let stripe = self.pending;
+            self.stripe(&stripe);
+            self.pending_len = 0;
+        }
+
+        let mut stripes = data.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.pending[..rest.len()].copy_from_slice(rest);
+        self.pending_len = rest.len();
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (acc, lane) in self.acc.iter_mut().zip(stripe.chunks_exact(8)) {
+            *acc = round(*acc, read_u64(lane));
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        let mut h = if self.total_len >= 32 {
+            let [a, b, c, d] = self.acc;
+            let mut h = a
+                .rotate_left(1)
+                .wrapping_add(b.rotate_left(7))
+                .wrapping_add(c.rotate_left(12))
+                .wrapping_add(d.rotate_left(18));
+            for acc in self.acc {
+                h = (h ^ round(0, acc))
+                    .wrapping_mul(PRIME1)
+                    .wrapping_add(PRIME4);
+            }
+            h
+        } else {
+            self.seed.wrapping_add(PRIME5)
+        };
+        h = h.wrapping_add(self.total_len);
+
+        let mut rest = &self.pending[..self.pending_len];
+        while rest.len() >= 8 {
+            h ^= round(0, read_u64(rest));
+            h = h.rotate_left(27).wrapping_mul(PRIME1).wrapping_add(PRIME4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            h ^= u64::from(u32::from_le_bytes(rest[..4].try_into().unwrap())).wrapping_mul(PRIME1);
+            h = h.rotate_left(23).wrapping_mul(PRIME2).wrapping_add(PRIME3);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            h ^= u64::from(byte).wrapping_mul(PRIME5);
+            h = h.rotate_left(11).wrapping_mul(PRIME1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(PRIME2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(PRIME3);
+        h ^ (h >> 32)
+    }
+}
+
+fn round(acc: u64, lane: u64) -> u64 {
+    acc.wrapping_add(lane.wrapping_mul(PRIME2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME1)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Xxh64;
+
+    const PRIME32: u64 = 2_654_435_761;
+
+    fn hash(data: &[u8], seed: u64) -> u64 {
+        let mut hasher = Xxh64::new(seed);
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    /// The buffer xxhsum's sanity check hashes prefixes of.
+    fn sanity_buffer() -> Vec<u8> {
+        let mut byte_gen = PRIME32 as u32;
+        (0..2367)
+            .map(|_| {
+                let byte = (byte_gen >> 24) as u8;
+                byte_gen = byte_gen.wrapping_mul(byte_gen);
+                byte
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reference_vectors() {
+        let buf = sanity_buffer();
+        let vectors = [
+            (0, 0, 0xef46_db37_51d8_e999),
+            (0, PRIME32, 0xac75_fda2_929b_17ef),
+            (1, 0, 0x4fce_394c_c889_52d8),
+            (1, PRIME32, 0x7398_40cb_819f_a723),
+            (14, 0, 0xcffa_8db8_81bc_3a3d),
+            (14, PRIME32, 0x5b96_1158_5efc_c9cb),
+            (222, 0, 0x9dd5_0788_0deb_b03d),
+            (222, PRIME32, 0xdc51_5172_b8ee_0600),
+            (2367, 0, 0x2e71_7c2b_b15c_0a8a),
+            (2367, PRIME32, 0xf7b7_f152_e920_c87c),
+        ];
+        for (len, seed, expected) in vectors {
+            assert_eq!(hash(&buf[..len], seed), expected, "len {len} seed {seed}");
+        }
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(hash(b"a", 0), 0xd24e_c4f1_a98c_6e5b);
+        assert_eq!(hash(b"abc", 0), 0x44bc_2cf5_ad77_0999);
+        assert_eq!(
+            hash(b"Nobody inspects the spammish repetition", 0),
+            0xfbce_a83c_8a37_8bf1
+        );
+    }
+
+    #[test]
+    fn streaming_matches_one_shot() {
+        let buf = sanity_buffer();
+        for piece in [1, 3, 7, 31, 32, 33, 100] {
+            let mut hasher = Xxh64::new(0);
+            for chunk in buf.chunks(piece) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finish(), hash(&buf, 0), "pieces of {piece}");
+        }
+    }
+
+    #[test]
+    fn repeated_block_checksum() {
+        let block: Vec<u8> = (0..=255).collect();
+        let body: Vec<u8> = block.iter().copied().cycle().take(1000).collect();
+        assert_eq!(crate::checksum(&block, 1000), hash(&body, 0));
+        assert_eq!(crate::checksum(&block, 0), 0xef46_db37_51d8_e999);
+    }
+}
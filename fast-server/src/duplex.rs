@@ -0,0 +1,109 @@
+use std::time::{Duration, Instant};
+
+use bench_payload::json::{Object, Value};
+use compio::{
+    BufResult,
+    buf::{IntoInner as _, IoBuf as _},
+    io::{AsyncRead as _, AsyncWriteExt as _},
+    net::TcpStream,
+};
+use futures_util::future::join;
+
+use crate::http::{self, Conn, ResponseHead};
+use crate::payload::{self, CHUNK_SIZE, Content, SendPath};
+use crate::route::{Download, Framing};
+
+/// `Upgrade` token a client has to ask for to enter duplex mode.
+pub const PROTOCOL: &str = "bench-duplex";
+
+#[derive(Debug, Clone)]
+pub struct Duplex {
+    /// Bytes the server sends.
+    pub download: u64,
+    /// Bytes the server expects from the client.
+    pub upload: u64,
+    /// What the server sends.
+    pub content: Content,
+}
+
+impl Duplex {
+    /// The body the server sends.
+    pub fn body(&self) -> Download {
+        Download {
+            size: self.download,
+            framing: Framing::Length,
+            send_path: SendPath::Plain,
+            content: self.content,
+            checksum: false,
+            duration: None,
+        }
+    }
+}
+
+/// Switches the connection to duplex mode. After the `101` response the server
+/// sends `download` bytes while reading `upload` bytes at the same time, then
+/// writes one JSON line with the throughput of each direction. The connection
+/// cannot be reused afterwards.
+pub async fn run(conn: &mut Conn, duplex: &Duplex) -> Result<(), http::Error> {
+    let head = ResponseHead::new(101, "Switching Protocols").upgrade(PROTOCOL);
+    conn.stream.write_all(head).await.0?;
+
+    let start = Instant::now();
+    let early = conn.take_buffered(duplex.upload);
+    let (sent, received) = join(
+        send(&conn.stream, duplex.body(), start),
+        receive(&conn.stream, duplex.upload - early, start),
+    )
+    .await;
+    let send_time = sent?;
+    let recv_time = received?;
+
+    let report = Value::from(
+        Object::new()
+            .field("download", &direction_json(duplex.download, send_time))
+            .field("upload", &direction_json(duplex.upload, recv_time)),
+    );
+    let report = format!("{report}\n");
+    conn.stream.write_all(report).await.0?;
+    Ok(())
+}
+
+/// Writes `download`, returning when the last byte was handed to the kernel.
+async fn send(
+    stream: &TcpStream,
+    download: Download,
+    start: Instant,
+) -> Result<Duration, http::Error> {
+    payload::send_body(stream, &download).await?;
+    Ok(start.elapsed())
+}
+
+/// Reads and drops `len` bytes, returning when the last one arrived.
+async fn receive(
+    mut stream: &TcpStream,
+    len: u64,
+    start: Instant,
+) -> Result<Duration, http::Error> {
+    let mut scratch = Vec::with_capacity(CHUNK_SIZE);
+    let mut left = len;
+    while left > 0 {
+        let want = left.min(CHUNK_SIZE as u64) as usize;
+        let BufResult(result, slice) = stream.read(scratch.slice(..want)).await;
+        scratch = slice.into_inner();
+        match result? {
+            0 => return Err(http::Error::Malformed("connection closed mid-upload")),
+            n => left -= n as u64,
+        }
+    }
+    Ok(start.elapsed())
+}
+
+fn direction_json(bytes: u64, elapsed: Duration) -> Object {
+    let secs = elapsed.as_secs_f64();
+    let bytes_per_sec = if secs > 0.0 { bytes as f64 / secs } else { 0.0 };
+    Object::new()
+        .field("bytes", &bytes)
+        .field("elapsed_secs", &secs)
+        .field("bytes_per_sec", &bytes_per_sec.round())
+        .field("mib_per_sec", &(bytes_per_sec / (1024.0 * 1024.0)))
+}
@@ -0,0 +1,534 @@
+use std::{fmt, io, time::Instant};
+
+use bench_payload::http::{self as head, LineError, MAX_HEAD_SIZE, READ_SIZE};
+use compio::{
+    BufResult,
+    buf::{IntoInner as _, IoBuf as _},
+    io::{AsyncRead as _, AsyncWriteExt as _},
+    net::TcpStream,
+};
+
+/// Body bytes are read straight into this much scratch space and dropped.
+const DISCARD_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Malformed(&'static str),
+    HeadTooLarge,
+    Unsupported(&'static str),
+}
+
+impl Error {
+    /// Status line to answer with before dropping the connection.
+    pub fn status(&self) -> (u16, &'static str) {
+        match self {
+            Error::Io(_) | Error::Malformed(_) => (400, "Bad Request"),
+            Error::HeadTooLarge => (431, "Request Header Fields Too Large"),
+            Error::Unsupported(_) => (501, "Not Implemented"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {e}"),
+            Error::Malformed(what) => write!(f, "malformed request: {what}"),
+            Error::HeadTooLarge => write!(f, "request head exceeds {MAX_HEAD_SIZE} bytes"),
+            Error::Unsupported(what) => write!(f, "unsupported: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<LineError> for Error {
+    fn from(e: LineError) -> Self {
+        match e {
+            LineError::Io(e) => Error::Io(e),
+            LineError::TooLong => Error::HeadTooLarge,
+            LineError::NotUtf8 => Error::Malformed("non utf-8 line"),
+            LineError::Closed => Error::Malformed("connection closed mid-body"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Body {
+    None,
+    Length(u64),
+    Chunked,
+}
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    pub version: Version,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// First header named `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Whether `name` lists `token` in any of its comma separated values.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .flat_map(|(_, v)| v.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            Version::Http11 => !self.has_token("connection", "close"),
+            Version::Http10 => self.has_token("connection", "keep-alive"),
+        }
+    }
+
+    /// How the request body is framed, per RFC 9112 section 6.
+    pub fn body(&self) -> Result<Body, Error> {
+        if let Some(te) = self.header("transfer-encoding") {
+            if self.header("content-length").is_some() {
+                return Err(Error::Malformed(
+                    "both transfer-encoding and content-length",
+                ));
+            }
+            let last = te.rsplit(',').next().unwrap_or_default().trim();
+            if !last.eq_ignore_ascii_case("chunked") {
+                return Err(Error::Unsupported("transfer-encoding"));
+            }
+            return Ok(Body::Chunked);
+        }
+
+        let mut length = None;
+        for (_, value) in self
+            .headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case("content-length"))
+        {
+            for v in value.split(',') {
+                let v = v.trim();
+                if v.is_empty() || !v.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(Error::Malformed("content-length"));
+                }
+                let n: u64 = v.parse().map_err(|_| Error::Malformed("content-length"))?;
+                if length.is_some_and(|l| l != n) {
+                    return Err(Error::Malformed("conflicting content-length"));
+                }
+                length = Some(n);
+            }
+        }
+
+        Ok(match length {
+            None | Some(0) => Body::None,
+            Some(n) => Body::Length(n),
+        })
+    }
+}
+
+/// A client connection together with the bytes read but not consumed yet,
+/// so pipelined requests and requests split across reads both work.
+pub struct Conn {
+    pub stream: TcpStream,
+    buf: Vec<u8>,
+    scratch: Vec<u8>,
+    /// When the last read into `buf` returned.
+    filled: Instant,
+    /// When the first byte of the last request head arrived.
+    request_start: Instant,
+}
+
+impl Conn {
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            buf: Vec::with_capacity(READ_SIZE),
+            scratch: Vec::new(),
+            filled: Instant::now(),
+            request_start: Instant::now(),
+        }
+    }
+
+    /// Reads the next request head. Returns `None` once the client closed the
+    /// connection cleanly between requests.
+    pub async fn read_request(&mut self) -> Result<Option<Request>, Error> {
+        let mut start = None;
+        loop {
+            // RFC 9112 2.2: ignore empty lines received before a request-line.
+            let skip = self
+                .buf
+                .iter()
+                .take_while(|&&b| b == b'\r' || b == b'\n')
+                .count();
+            self.buf.drain(..skip);
+            // Pipelined bytes arrived with the read that brought them in.
+            if self.buf.is_empty() {
+                start = None;
+            } else if start.is_none() {
+                start = Some(self.filled);
+            }
+
+            if let Some((req, len)) = parse_request(&self.buf)? {
+                self.buf.drain(..len);
+                self.request_start = start.unwrap_or(self.filled);
+                return Ok(Some(req));
+            }
+            if self.buf.len() >= MAX_HEAD_SIZE {
+                return Err(Error::HeadTooLarge);
+            }
+            if self.fill().await? == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(Error::Malformed("connection closed mid-request"));
+            }
+        }
+    }
+
+    /// Reads and drops a request body framed as `body`, returning its length.
+    pub async fn read_body(&mut self, body: Body) -> Result<u64, Error> {
+        match body {
+            Body::None => Ok(0),
+            Body::Length(len) => self.discard(len).await.map(|()| len),
+            Body::Chunked => self.discard_chunked().await,
+        }
+    }
+
+    /// Throws away exactly `len` body bytes. Whatever is not buffered yet is
+    /// read into scratch space, never past the end of the body.
+    async fn discard(&mut self, len: u64) -> Result<(), Error> {
+        let mut left = len - self.take_buffered(len);
+        if left == 0 {
+            return Ok(());
+        }
+
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.reserve(DISCARD_SIZE);
+        let result = loop {
+            let want = left.min(scratch.capacity() as u64) as usize;
+            let BufResult(result, slice) = self.stream.read(scratch.slice(..want)).await;
+            scratch = slice.into_inner();
+            match result {
+                Ok(0) => break Err(Error::Malformed("connection closed mid-body")),
+                Ok(n) => left -= n as u64,
+                Err(e) => break Err(e.into()),
+            }
+            if left == 0 {
+                break Ok(());
+            }
+        };
+        self.scratch = scratch;
+        result
+    }
+
+    async fn discard_chunked(&mut self) -> Result<u64, Error> {
+        let mut total = 0u64;
+        loop {
+            let line = self.read_line().await?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = u64::from_str_radix(size, 16).map_err(|_| Error::Malformed("chunk size"))?;
+            if size == 0 {
+                // Skip trailer fields up to the terminating empty line.
+                while !self.read_line().await?.is_empty() {}
+                return Ok(total);
+            }
+            self.discard(size).await?;
+            total += size;
+            if !self.read_line().await?.is_empty() {
+                return Err(Error::Malformed("chunk data"));
+            }
+        }
+    }
+
+    async fn read_line(&mut self) -> Result<String, Error> {
+        Ok(head::read_line(&mut self.stream, &mut self.buf).await?)
+    }
+
+    /// Takes up to `max` bytes that were read ahead of the current request,
+    /// e.g. data a client sent right after an upgrade request.
+    pub fn take_buffered(&mut self, max: u64) -> u64 {
+        let take = max.min(self.buf.len() as u64) as usize;
+        self.buf.drain(..take);
+        take as u64
+    }
+
+    async fn fill(&mut self) -> io::Result<usize> {
+        let n = head::fill(&mut self.stream, &mut self.buf).await?;
+        self.filled = Instant::now();
+        Ok(n)
+    }
+
+    /// When the first byte of the request [`Conn::read_request`] returned
+    /// last arrived, as close as the reads can tell.
+    pub fn request_start(&self) -> Instant {
+        self.request_start
+    }
+
+    pub async fn send_error(&mut self, status: (u16, &str), message: &str) -> io::Result<()> {
+        let body = format!("{message}\n");
+        let head = ResponseHead::new(status.0, status.1)
+            .header("Content-Type", "text/plain")
+            .header("Content-Length", body.len())
+            .finish(false);
+        self.stream.write_all(head + &body).await.0
+    }
+}
+
+/// Parses one request head from the start of `buf`. Returns the request and
+/// the number of bytes it occupied, or `None` if the head is not complete.
+fn parse_request(buf: &[u8]) -> Result<Option<(Request, usize)>, Error> {
+    let Some(end) = head::find_head_end(buf) else {
+        return Ok(None);
+    };
+    let head = std::str::from_utf8(&buf[..end]).map_err(|_| Error::Malformed("non utf-8 head"))?;
+    let mut lines = head.split('\n').map(|l| l.strip_suffix('\r').unwrap_or(l));
+
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Error::Malformed("request line"));
+    };
+    if method.is_empty() || !method.bytes().all(is_tchar) {
+        return Err(Error::Malformed("method"));
+    }
+    if target.is_empty() {
+        return Err(Error::Malformed("request target"));
+    }
+    let version = match version {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        v if v.starts_with("HTTP/") => return Err(Error::Unsupported("http version")),
+        _ => return Err(Error::Malformed("http version")),
+    };
+
+    let mut headers = Vec::new();
+    for line in lines.take_while(|l| !l.is_empty()) {
+        if line.starts_with([' ', '\t']) {
+            return Err(Error::Malformed("obsolete line folding"));
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(Error::Malformed("header line"));
+        };
+        if name.is_empty() || !name.bytes().all(is_tchar) {
+            return Err(Error::Malformed("header name"));
+        }
+        headers.push((name.to_owned(), value.trim_matches([' ', '\t']).to_owned()));
+    }
+
+    let req = Request {
+        method: method.to_owned(),
+        target: target.to_owned(),
+        version,
+        headers,
+    };
+    Ok(Some((req, end)))
+}
+
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+pub struct ResponseHead {
+    buf: String,
+}
+
+impl ResponseHead {
+    pub fn new(status: u16, reason: &str) -> Self {
+        Self {
+            buf: format!("HTTP/1.1 {status} {reason}\r\n"),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: impl fmt::Display) -> Self {
+        use fmt::Write as _;
+        _ = write!(self.buf, "{name}: {value}\r\n");
+        self
+    }
+
+    /// Ends a `101 Switching Protocols` head handing the connection over to
+    /// `protocol`.
+    pub fn upgrade(self, protocol: &str) -> String {
+        self.header("Upgrade", protocol)
+            .header("Connection", "Upgrade")
+            .buf
+            + "\r\n"
+    }
+
+    pub fn finish(self, keep_alive: bool) -> String {
+        let connection = if keep_alive { "keep-alive" } else { "close" };
+        self.header("Connection", connection).buf + "\r\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn parse(head: &str) -> Result<Option<(Request, usize)>, Error> {
+        parse_request(head.as_bytes())
+    }
+
+    fn request(head: &str) -> Request {
+        parse(head).unwrap().expect("complete head").0
+    }
+
+    #[test]
+    fn request_line_and_headers() {
+        let head =
+            "GET /bench?size=1MiB HTTP/1.1\r\nHost: x\r\nX-Empty:\r\nAccept:  */* \t\r\n\r\nnext";
+        let (req, len) = parse(head).unwrap().unwrap();
+        assert_eq!(len, head.len() - "next".len());
+        assert_eq!(req.method, "GET");
+        assert_eq!(req.target, "/bench?size=1MiB");
+        assert_eq!(req.version, Version::Http11);
+        assert_eq!(req.header("host"), Some("x"));
+        assert_eq!(req.header("x-empty"), Some(""));
+        assert_eq!(req.header("ACCEPT"), Some("*/*"));
+        assert_eq!(req.header("missing"), None);
+    }
+
+    #[test]
+    fn bare_lf() {
+        let req = request("HEAD / HTTP/1.0\nConnection: keep-alive\n\n");
+        assert_eq!(req.method, "HEAD");
+        assert_eq!(req.version, Version::Http10);
+        assert_eq!(req.header("connection"), Some("keep-alive"));
+    }
+
+    #[test]
+    fn incomplete_head() {
+        assert!(parse("").unwrap().is_none());
+        assert!(parse("GET / HTTP/1.1\r\n").unwrap().is_none());
+        assert!(parse("GET / HTTP/1.1\r\nHost: x\r\n").unwrap().is_none());
+    }
+
+    #[test]
+    fn malformed_request_line() {
+        for head in [
+            "GET /\r\n\r\n",
+            "GET  / HTTP/1.1\r\n\r\n",
+            "GET / HTTP/1.1 extra\r\n\r\n",
+            "G(T / HTTP/1.1\r\n\r\n",
+            "GET / HTTP1.1\r\n\r\n",
+        ] {
+            assert!(matches!(parse(head), Err(Error::Malformed(_))), "{head:?}");
+        }
+        assert!(matches!(
+            parse("GET / HTTP/2.0\r\n\r\n"),
+            Err(Error::Unsupported("http version"))
+        ));
+    }
+
+    #[test]
+    fn malformed_headers() {
+        for (head, what) in [
+            ("GET / HTTP/1.1\r\nHost x\r\n\r\n", "header line"),
+            ("GET / HTTP/1.1\r\n: x\r\n\r\n", "header name"),
+            ("GET / HTTP/1.1\r\nBad Name: x\r\n\r\n", "header name"),
+            (
+                "GET / HTTP/1.1\r\nA: b\r\n c\r\n\r\n",
+                "obsolete line folding",
+            ),
+        ] {
+            assert!(
+                matches!(parse(head), Err(Error::Malformed(w)) if w == what),
+                "{head:?}"
+            );
+        }
+        assert!(matches!(
+            parse_request(b"GET /\xff HTTP/1.1\r\n\r\n"),
+            Err(Error::Malformed("non utf-8 head"))
+        ));
+    }
+
+    #[test]
+    fn keep_alive() {
+        assert!(request("GET / HTTP/1.1\r\n\r\n").keep_alive());
+        assert!(!request("GET / HTTP/1.1\r\nConnection: Close\r\n\r\n").keep_alive());
+        assert!(!request("GET / HTTP/1.0\r\n\r\n").keep_alive());
+        assert!(request("GET / HTTP/1.0\r\nConnection: foo, Keep-Alive\r\n\r\n").keep_alive());
+    }
+
+    #[test]
+    fn body_framing() {
+        let body = |headers: &str| request(&format!("POST / HTTP/1.1\r\n{headers}\r\n")).body();
+        assert_eq!(body("").unwrap(), Body::None);
+        assert_eq!(body("Content-Length: 0\r\n").unwrap(), Body::None);
+        assert_eq!(body("Content-Length: 42\r\n").unwrap(), Body::Length(42));
+        assert_eq!(
+            body("Content-Length: 42, 42\r\n").unwrap(),
+            Body::Length(42)
+        );
+        assert_eq!(
+            body("Transfer-Encoding: gzip, chunked\r\n").unwrap(),
+            Body::Chunked
+        );
+
+        for headers in [
+            "Content-Length: 42, 43\r\n",
+            "Content-Length: 42\r\nContent-Length: 43\r\n",
+            "Content-Length: -1\r\n",
+            "Content-Length: +1\r\n",
+            "Content-Length: 99999999999999999999\r\n",
+            "Transfer-Encoding: chunked\r\nContent-Length: 1\r\n",
+        ] {
+            assert!(
+                matches!(body(headers), Err(Error::Malformed(_))),
+                "{headers:?}"
+            );
+        }
+        assert!(matches!(
+            body("Transfer-Encoding: chunked, gzip\r\n"),
+            Err(Error::Unsupported("transfer-encoding"))
+        ));
+    }
+
+    #[test]
+    fn request_start_is_first_byte() {
+        compio::runtime::Runtime::new().unwrap().block_on(async {
+            let listener = compio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let client = thread::spawn(move || {
+                let mut stream = std::net::TcpStream::connect(addr).unwrap();
+                stream.write_all(b"POST /upload HTTP/1.1\r\n").unwrap();
+                thread::sleep(Duration::from_millis(100));
+                let rest = Instant::now();
+                stream.write_all(b"Content-Length: 0\r\n\r\n").unwrap();
+                rest
+            });
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut conn = Conn::new(stream);
+            let req = conn.read_request().await.unwrap().unwrap();
+            assert_eq!(req.target, "/upload");
+            let rest = client.join().unwrap();
+            assert!(conn.request_start() < rest);
+        });
+    }
+}
@@ -1,17 +1,36 @@
-use std::sync::Arc;
-use std::sync::atomic::AtomicUsize;
-use std::{env, sync::atomic::Ordering};
+mod duplex;
+mod http;
+mod payload;
+mod route;
+#[cfg(target_os = "linux")]
+mod sendfile;
+#[cfg(target_os = "linux")]
+mod uring;
 
-use compio::{
-    BufResult,
-    io::{AsyncRead, AsyncWriteExt as _},
-};
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::OnceLock;
+use std::{env, thread};
 
-static MAX_SEND_BYTES: AtomicUsize = AtomicUsize::new(1024 * 1024 * 1024 * 32); // 32 GiB
+use anyhow::Context as _;
+use bench_payload::json::{Object, Value};
+use socket2::{Domain, Protocol, Socket, Type};
 
-#[compio::main]
-async fn main() -> anyhow::Result<()> {
-    dotenvy::dotenv()?;
+use compio::io::AsyncWriteExt as _;
+
+use crate::http::{Body, Conn, Request, ResponseHead, Version};
+use crate::payload::{Content, SendPath};
+use crate::route::{Download, Framing, Route};
+
+/// Body of `GET /ping`.
+const PONG: &str = "pong\n";
+
+/// What a download gets unless the request overrides it, set once from the
+/// environment at startup.
+static DEFAULTS: OnceLock<Download> = OnceLock::new();
+
+fn main() -> anyhow::Result<()> {
+    _ = dotenvy::dotenv();
 
     // 从环境变量读取端口，默认 8080
     let port: u16 = env::var("HTTP_SERVER_PORT")
@@ -19,63 +38,364 @@ async fn main() -> anyhow::Result<()> {
         .and_then(|v| v.parse().ok())
         .unwrap_or(8089);
 
-    if let Some(bytes) = env::var("MAX_SEND_BYTES")
+    let size = env::var("MAX_SEND_BYTES")
         .ok()
-        .and_then(|b| b.parse::<usize>().ok())
-    {
-        MAX_SEND_BYTES.store(bytes, Ordering::Release);
+        .and_then(|b| b.parse::<u64>().ok())
+        .unwrap_or(1024 * 1024 * 1024 * 32); // 32 GiB
+
+    // RESPONSE_FRAMING=length 默认使用 Content-Length 而不是 chunked
+    let framing = env::var("RESPONSE_FRAMING")
+        .ok()
+        .and_then(|f| Framing::parse(&f))
+        .unwrap_or(Framing::Chunked);
+
+    // SEND_PATH=fixed|zerocopy 使用 io_uring registered buffer / SEND_ZC
+    let send_path = env::var("SEND_PATH")
+        .ok()
+        .and_then(|p| SendPath::parse(&p))
+        .unwrap_or(SendPath::Plain);
+
+    // PAYLOAD_FILE 在第一次 content=file 请求时才读入内存，sendfile 直接读文件，
+    // 未设置或不可用时 sendfile 使用全零的 memfd
+    let path = match env::var_os("PAYLOAD_FILE").map(std::path::PathBuf::from) {
+        Some(path) => match payload::set_payload_file(&path) {
+            Ok(len) => {
+                println!(
+                    "Payload file: {} ({})",
+                    path.display(),
+                    humansize::format_size(len, humansize::BINARY)
+                );
+                Some(path)
+            }
+            Err(e) => {
+                println!(
+                    "Payload file: {}: {e}, content=file is unavailable",
+                    path.display()
+                );
+                None
+            }
+        },
+        None => {
+            println!("Payload file: none, sendfile reads a zero-filled memfd");
+            None
+        }
+    };
+    #[cfg(target_os = "linux")]
+    sendfile::init(path.as_deref())?;
+
+    // PAYLOAD_CONTENT=zeros|random|pattern|file 默认的 body 内容，PAYLOAD_SEED 是 random 的种子
+    // 和 ?seed= 一样，单独的种子意味着 random
+    let explicit = env::var("PAYLOAD_CONTENT")
+        .ok()
+        .and_then(|c| Content::parse(&c));
+    let mut content = explicit.unwrap_or(Content::Zeros);
+    if let Some(seed) = env::var("PAYLOAD_SEED").ok().and_then(|s| s.parse().ok()) {
+        content = match explicit {
+            None | Some(Content::Random(_)) => Content::Random(seed),
+            Some(other) => {
+                anyhow::bail!("PAYLOAD_SEED only applies to random content, not {other}")
+            }
+        };
+    }
+    if content == Content::File && path.is_none() {
+        anyhow::bail!("PAYLOAD_CONTENT=file requires PAYLOAD_FILE");
     }
 
-    let bytes = MAX_SEND_BYTES.load(Ordering::Acquire);
+    let defaults = DEFAULTS.get_or_init(|| Download {
+        size,
+        framing,
+        send_path,
+        content,
+        checksum: false,
+        duration: None,
+    });
     println!(
-        "Send size: {} ({bytes} bytes)",
-        humansize::format_size(bytes, humansize::BINARY,)
+        "Default send size: {} ({size} bytes)",
+        humansize::format_size(size, humansize::BINARY,)
     );
+    println!("Default framing: {:?}", defaults.framing);
+    println!("Default content: {:?}", defaults.content);
+    println!(
+        "Default send path: {:?} (io_uring supports up to {:?})",
+        defaults.send_path.effective(defaults.content),
+        SendPath::supported()
+    );
+
+    // SERVER_THREADS 个 worker，每个都有自己的 compio runtime 和 SO_REUSEPORT listener
+    let threads = env::var("SERVER_THREADS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    let cpus = env::var("SERVER_PIN_CPUS")
+        .ok()
+        .and_then(|v| parse_cpu_list(&v, threads));
 
-    let listener = compio::net::TcpListener::bind(("127.0.0.1", port)).await?;
+    let listeners =
+        bind_listeners(port, threads).with_context(|| format!("bind 127.0.0.1:{port}"))?;
+    let port = listeners[0].local_addr()?.port();
+    match &cpus {
+        Some(cpus) => println!("Workers: {threads}, pinned to CPUs {cpus:?}"),
+        None => println!("Workers: {threads}"),
+    }
     println!("HTTP server running on 127.0.0.1:{}", port);
 
-    let listener = Arc::new(listener);
+    let workers = listeners
+        .into_iter()
+        .enumerate()
+        .map(|(i, listener)| {
+            let cpu = cpus.as_ref().map(|cpus| cpus[i % cpus.len()]);
+            thread::Builder::new()
+                .name(format!("fast-server-{i}"))
+                .spawn(move || run_worker(listener, cpu))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
 
-    loop {
-        let (stream, _) = listener.accept().await?;
-        compio::runtime::spawn(handle_client(stream)).detach();
+    for worker in workers {
+        worker
+            .join()
+            .map_err(|_| anyhow::anyhow!("worker thread panicked"))??;
     }
+    Ok(())
 }
 
-async fn handle_client(mut stream: compio::net::TcpStream) -> anyhow::Result<()> {
-    let buf = vec![0; 4096];
-    let result = stream.read(buf).await;
-    if result.0? == 0 {
-        return Ok(());
+/// `1`/`true` pins worker i to CPU i, otherwise a comma separated list of
+/// CPU ids that workers are assigned to round-robin.
+fn parse_cpu_list(value: &str, threads: usize) -> Option<Vec<usize>> {
+    match value.trim() {
+        "" | "0" | "false" => None,
+        "1" | "true" => Some((0..threads).collect()),
+        list => {
+            let cpus = list
+                .split(',')
+                .map(|c| c.trim().parse().ok())
+                .collect::<Option<Vec<usize>>>()?;
+            (!cpus.is_empty()).then_some(cpus)
+        }
     }
+}
 
-    let headers = "HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nContent-Type: application/octet-stream\r\nTransfer-Encoding: chunked\r\n\r\n";
-    stream.write_all(headers.as_bytes()).await.0?;
-    _ = stream.set_nodelay(true);
+/// Binds one listener per worker to the same port with SO_REUSEPORT so the
+/// kernel spreads incoming connections across them. With port 0 every worker
+/// shares the port the first bind was assigned.
+///
+/// The first listener binds without SO_REUSEPORT and only sets it afterwards,
+/// so a port that another SO_REUSEPORT process (such as a second fast-server)
+/// already holds fails with `EADDRINUSE` instead of being silently shared.
+fn bind_listeners(port: u16, count: usize) -> std::io::Result<Vec<std::net::TcpListener>> {
+    let mut addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let mut listeners = Vec::with_capacity(count);
+    for i in 0..count {
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_reuse_address(true)?;
+        socket.set_nonblocking(true)?;
+        if i == 0 {
+            socket.bind(&addr.into())?;
+            socket.set_reuse_port(true)?;
+        } else {
+            socket.set_reuse_port(true)?;
+            socket.bind(&addr.into())?;
+        }
+        socket.listen(1024)?;
+        let listener = std::net::TcpListener::from(socket);
+        addr = listener.local_addr()?;
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
 
-    let mut sent_count = 0;
+fn run_worker(listener: std::net::TcpListener, cpu: Option<usize>) -> anyhow::Result<()> {
+    let mut builder = compio::runtime::Runtime::builder();
+    if let Some(cpu) = cpu {
+        builder.thread_affinity(HashSet::from([cpu]));
+    }
+    let runtime = builder.build()?;
 
-    let max_bytes = MAX_SEND_BYTES.load(Ordering::Acquire);
-    while sent_count < max_bytes {
-        // 1 MiB chunk
-        stream.write_all(b"100000\r\n").await.0?;
-        let mut chunk = vec![0u8; 1024 * 1024];
-        let BufResult(result, c) = stream.write_all(chunk).await;
+    runtime.block_on(async move {
+        let listener = compio::net::TcpListener::from_std(listener)?;
+        loop {
+            let (stream, _) = listener.accept().await?;
+            compio::runtime::spawn(handle_client(stream)).detach();
+        }
+    })
+}
 
-        chunk = c;
-        _ = stream.write_all(b"\r\n").await.0;
+async fn handle_client(stream: compio::net::TcpStream) -> anyhow::Result<()> {
+    _ = stream.set_nodelay(true);
+    let mut conn = Conn::new(stream);
 
+    loop {
+        let result = match conn.read_request().await {
+            Ok(Some(req)) => serve(&mut conn, &req).await,
+            Ok(None) => break,
+            Err(e) => Err(e),
+        };
         match result {
-            Ok(_) => sent_count += chunk.len(),
-            Err(_) => {
-                stream.close().await?;
-                return Ok(());
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(http::Error::Io(e)) => return Err(e.into()),
+            Err(e) => {
+                _ = conn.send_error(e.status(), &e.to_string()).await;
+                break;
             }
         }
     }
 
-    stream.write_all(b"0\r\n\r\n").await.0?;
+    conn.stream.close().await?;
+    Ok(())
+}
+
+/// Answers one request. Returns whether the connection can be reused.
+async fn serve(conn: &mut Conn, req: &Request) -> Result<bool, http::Error> {
+    let keep_alive = req.keep_alive();
+    let body = req.body()?;
 
+    let mut route = match route::route(
+        &req.method,
+        &req.target,
+        DEFAULTS.get().expect("defaults are set in main"),
+    ) {
+        Ok(route) => route,
+        Err(e) => {
+            let message = format!("{} {}: {e}", req.method, req.target);
+            conn.send_error(e.status(), &message).await?;
+            return Ok(false);
+        }
+    };
+
+    // HTTP/1.0 不认识 chunked
+    if let Route::Download(download) = &mut route
+        && req.version == Version::Http10
+    {
+        download.http10();
+    }
+
+    // content=file 第一次用到时才读 PAYLOAD_FILE
+    let loaded = match &route {
+        Route::Download(download) if req.method != "HEAD" => payload::load_corpus(download).await,
+        Route::Duplex(duplex) => payload::load_corpus(&duplex.body()).await,
+        _ => Ok(()),
+    };
+    if let Err(e) = loaded {
+        let message = format!("{} {}: {e}", req.method, req.target);
+        conn.send_error((500, "Internal Server Error"), &message)
+            .await?;
+        return Ok(false);
+    }
+
+    match route {
+        Route::Download(download) => {
+            conn.read_body(body).await?;
+            let head_only = req.method == "HEAD";
+            let keep_alive = keep_alive && download.framing != Framing::Close;
+            let completed = send_payload(conn, &download, head_only, keep_alive).await?;
+            Ok(completed && keep_alive)
+        }
+        Route::Upload => {
+            receive_upload(conn, req, body, keep_alive).await?;
+            Ok(keep_alive)
+        }
+        Route::Ping => {
+            conn.read_body(body).await?;
+            let head = ResponseHead::new(200, "OK")
+                .header("Content-Type", "text/plain")
+                .header("Content-Length", PONG.len())
+                .finish(keep_alive);
+            let response = if req.method == "HEAD" {
+                head
+            } else {
+                head + PONG
+            };
+            conn.stream.write_all(response).await.0?;
+            Ok(keep_alive)
+        }
+        Route::Duplex(duplex) => {
+            if !req.has_token("upgrade", duplex::PROTOCOL) {
+                let message = format!("expected Upgrade: {}", duplex::PROTOCOL);
+                conn.send_error((426, "Upgrade Required"), &message).await?;
+                return Ok(false);
+            }
+            duplex::run(conn, &duplex).await?;
+            Ok(false)
+        }
+    }
+}
+
+/// Streams `download.size` bytes, either chunked or after a `Content-Length`,
+/// or chunked until `download.duration` is up. HTTP/1.0 clients get the
+/// latter until the connection closes instead.
+/// Returns `false` if the client went away before the body was complete.
+async fn send_payload(
+    conn: &mut Conn,
+    download: &Download,
+    head_only: bool,
+    keep_alive: bool,
+) -> Result<bool, http::Error> {
+    let stream = &mut conn.stream;
+
+    let headers = ResponseHead::new(200, "OK").header("Content-Type", "application/octet-stream");
+    let headers = match download.framing {
+        Framing::Chunked => {
+            let headers = headers.header("Transfer-Encoding", "chunked");
+            if download.checksum {
+                headers.header("Trailer", bench_payload::CHECKSUM_TRAILER)
+            } else {
+                headers
+            }
+        }
+        Framing::Length => headers.header("Content-Length", download.size),
+        Framing::Close => headers,
+    };
+    stream.write_all(headers.finish(keep_alive)).await.0?;
+
+    if head_only {
+        return Ok(true);
+    }
+
+    match payload::send_body(stream, download).await {
+        Ok(()) => Ok(true),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Drains the request body as fast as possible and answers with the number
+/// of bytes received, the time that took and the resulting throughput.
+async fn receive_upload(
+    conn: &mut Conn,
+    req: &Request,
+    body: Body,
+    keep_alive: bool,
+) -> Result<(), http::Error> {
+    if req.has_token("expect", "100-continue") {
+        conn.stream
+            .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+            .await
+            .0?;
+    }
+
+    // From the request's first byte: part of the body may have come in with
+    // the head, before the request was even routed.
+    let start = conn.request_start();
+    let received = conn.read_body(body).await?;
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let bytes_per_sec = if elapsed > 0.0 {
+        received as f64 / elapsed
+    } else {
+        0.0
+    };
+    let json = Value::from(
+        Object::new()
+            .field("bytes_received", &received)
+            .field("elapsed_secs", &elapsed)
+            .field("bytes_per_sec", &bytes_per_sec.round())
+            .field("mib_per_sec", &(bytes_per_sec / (1024.0 * 1024.0))),
+    );
+    let json = format!("{json}\n");
+    let head = ResponseHead::new(200, "OK")
+        .header("Content-Type", "application/json")
+        .header("Content-Length", json.len())
+        .finish(keep_alive);
+    conn.stream.write_all(head + &json).await.0?;
     Ok(())
 }
@@ -0,0 +1,296 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    sync::OnceLock,
+    time::Instant,
+};
+
+use compio::{
+    buf::{IoBuf as _, Slice},
+    io::AsyncWriteExt as _,
+    net::TcpStream,
+};
+
+pub use bench_payload::Content;
+use bench_payload::DEFAULT_SEED;
+
+use crate::route::{Download, Framing};
+#[cfg(target_os = "linux")]
+use crate::{sendfile, uring};
+
+/// Largest body slice handed to a single write.
+pub const CHUNK_SIZE: usize = bench_payload::BLOCK_SIZE;
+
+/// `PAYLOAD_FILE` and its length, set once at startup.
+static PAYLOAD_FILE: OnceLock<(PathBuf, u64)> = OnceLock::new();
+
+/// `PAYLOAD_FILE` read into memory for `content=file`, on first use.
+static CORPUS: OnceLock<Arc<[u8]>> = OnceLock::new();
+
+/// Room for a 64-bit chunk size in hex plus CRLF.
+pub type ChunkHead = [u8; 18];
+
+/// How body bytes reach the socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SendPath {
+    /// Owned-buffer writes through compio.
+    Plain,
+    /// io_uring writes from a registered buffer.
+    Fixed,
+    /// io_uring `SEND_ZC` from a registered buffer.
+    ZeroCopy,
+    /// sendfile(2) from `PAYLOAD_FILE` or a memfd, see [`crate::sendfile`].
+    Sendfile,
+}
+
+impl SendPath {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "plain" => Some(SendPath::Plain),
+            "fixed" => Some(SendPath::Fixed),
+            "zerocopy" | "zc" => Some(SendPath::ZeroCopy),
+            "sendfile" => Some(SendPath::Sendfile),
+            _ => None,
+        }
+    }
+
+    /// Fastest io_uring path the running kernel supports.
+    pub fn supported() -> Self {
+        #[cfg(target_os = "linux")]
+        return uring::supported();
+        #[cfg(not(target_os = "linux"))]
+        return SendPath::Plain;
+    }
+
+    /// The path actually taken for `self` when sending `content`, falling
+    /// back to a slower one if the kernel or configuration lacks it.
+    pub fn effective(self, content: Content) -> Self {
+        match self {
+            SendPath::Plain => SendPath::Plain,
+            SendPath::Fixed | SendPath::ZeroCopy => self.min(Self::supported()),
+            #[cfg(target_os = "linux")]
+            SendPath::Sendfile if sendfile::source().is_some_and(|s| s.holds(content)) => {
+                SendPath::Sendfile
+            }
+            SendPath::Sendfile => SendPath::Plain,
+        }
+    }
+}
+
+/// The block a body of `content` repeats. Blocks other than a non-default
+/// random seed are built once and shared by every connection on every worker,
+/// so the send path neither allocates nor fills memory per chunk.
+pub fn buffer(content: Content) -> Arc<[u8]> {
+    static ZEROS: OnceLock<Arc<[u8]>> = OnceLock::new();
+    static PATTERN: OnceLock<Arc<[u8]>> = OnceLock::new();
+    static RANDOM: OnceLock<Arc<[u8]>> = OnceLock::new();
+
+    let block = || content.block().expect("generated content").into();
+    match content {
+        Content::Zeros => ZEROS.get_or_init(block),
+        Content::Pattern => PATTERN.get_or_init(block),
+        Content::Random(DEFAULT_SEED) => RANDOM.get_or_init(block),
+        Content::Random(_) => return block(),
+        Content::File => CORPUS.get().expect("the corpus is loaded before a body"),
+    }
+    .clone()
+}
+
+/// Makes `path` the `PAYLOAD_FILE` for `content=file` and `GET /file`
+/// without reading it yet. Only the first call has an effect.
+pub fn set_payload_file(path: &Path) -> io::Result<u64> {
+    let len = std::fs::metadata(path)?.len();
+    if len == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is empty", path.display()),
+        ));
+    }
+    Ok(PAYLOAD_FILE.get_or_init(|| (path.to_owned(), len)).1)
+}
+
+/// Length of the configured `PAYLOAD_FILE`, if any.
+pub fn payload_file_len() -> Option<u64> {
+    PAYLOAD_FILE.get().map(|&(_, len)| len)
+}
+
+/// Reads `PAYLOAD_FILE` into memory the first time a body of `download` needs
+/// it. sendfile reads the file through its own fd, so there the corpus is only
+/// loaded for a checksum.
+pub async fn load_corpus(download: &Download) -> io::Result<()> {
+    let sendfile = download.send_path.effective(download.content) == SendPath::Sendfile;
+    if download.content != Content::File
+        || (sendfile && !download.checksum)
+        || CORPUS.get().is_some()
+    {
+        return Ok(());
+    }
+    let (path, _) = PAYLOAD_FILE
+        .get()
+        .ok_or_else(|| io::Error::other("content=file requires PAYLOAD_FILE"))?;
+    let bytes = compio::runtime::spawn_blocking(move || std::fs::read(path))
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e))?;
+    if bytes.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is empty", path.display()),
+        ));
+    }
+    CORPUS.get_or_init(|| bytes.into());
+    Ok(())
+}
+
+/// Writes the body of `download` over its send path, falling back to a slower
+/// path if the requested one is unavailable. A chunked body ends with the
+/// checksum trailer if the request asked for one.
+pub async fn send_body(mut stream: &TcpStream, download: &Download) -> io::Result<()> {
+    let &Download {
+        size: len,
+        framing,
+        send_path,
+        content,
+        checksum,
+        duration,
+    } = download;
+    let path = send_path.effective(content);
+    // Not taken on the sendfile path unless for the checksum, see
+    // `load_corpus`.
+    let buf = || buffer(content);
+    let deadline = duration.map(|d| Instant::now() + d);
+
+    // Hashed on the blocking pool while the body is being sent, unless a
+    // deadline leaves the length open until the end.
+    let hashing = (checksum && deadline.is_none()).then(|| {
+        let buf = buf();
+        compio::runtime::spawn_blocking(move || bench_payload::checksum(&buf, len))
+    });
+
+    #[cfg(target_os = "linux")]
+    let sent = match path {
+        SendPath::Plain => send_body_plain(stream, len, framing, deadline, buf()).await?,
+        SendPath::Sendfile => sendfile::send_body(stream, len, framing, deadline).await?,
+        path => uring::send_body(stream, len, framing, deadline, path, buf()).await?,
+    };
+    #[cfg(not(target_os = "linux"))]
+    let sent = send_body_plain(stream, len, framing, deadline, buf()).await?;
+
+    if framing == Framing::Chunked {
+        let mut last = String::from("0\r\n");
+        if checksum {
+            let sum = match hashing {
+                Some(hashing) => hashing.await,
+                None => {
+                    let buf = buf();
+                    compio::runtime::spawn_blocking(move || bench_payload::checksum(&buf, sent))
+                        .await
+                }
+            }
+            .unwrap_or_else(|e| std::panic::resume_unwind(e));
+            last += &format!(
+                "{}: {}\r\n",
+                bench_payload::CHECKSUM_TRAILER,
+                bench_payload::format_checksum(sum)
+            );
+        }
+        last += "\r\n";
+        stream.write_all(last).await.0?;
+    }
+    Ok(())
+}
+
+/// Whether a body that is `sent` bytes into `len` has more to send before
+/// `deadline`.
+pub fn more(sent: u64, len: u64, deadline: Option<Instant>) -> bool {
+    sent < len && deadline.is_none_or(|deadline| Instant::now() < deadline)
+}
+
+/// A chunk's size line, data and trailing CRLF go out in one vectored write.
+/// The last chunk is left to the caller. Returns the body bytes sent.
+async fn send_body_plain(
+    mut stream: &TcpStream,
+    len: u64,
+    framing: Framing,
+    deadline: Option<Instant>,
+    buf: Arc<[u8]>,
+) -> io::Result<u64> {
+    let mut sent = 0;
+    while more(sent, len, deadline) {
+        let (offset, n) = next_chunk(sent, len, buf.len());
+        let data = buf.clone().slice(offset..offset + n);
+        match framing {
+            Framing::Length | Framing::Close => stream.write_all(data).await.0?,
+            Framing::Chunked => {
+                let bufs = (chunk_head(n), (data, (&b"\r\n"[..],)));
+                stream.write_vectored_all(bufs).await.0?;
+            }
+        }
+        sent += n as u64;
+    }
+    Ok(sent)
+}
+
+/// Where in a `buf_len` byte buffer the chunk starting at body offset `sent`
+/// begins, and how long it is. Chunks stop at the end of the buffer so that
+/// each one is a single contiguous slice.
+pub fn next_chunk(sent: u64, len: u64, buf_len: usize) -> (usize, usize) {
+    let offset = (sent % buf_len as u64) as usize;
+    let n = (len - sent).min((buf_len - offset).min(CHUNK_SIZE) as u64) as usize;
+    (offset, n)
+}
+
+/// The size line of a `len` byte chunk, formatted without allocating.
+pub fn chunk_head(len: usize) -> Slice<ChunkHead> {
+    let mut head = ChunkHead::default();
+    let written = {
+        let mut cursor = &mut head[..];
+        _ = io::Write::write_fmt(&mut cursor, format_args!("{len:x}\r\n"));
+        size_of::<ChunkHead>() - cursor.len()
+    };
+    head.slice(..written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks() {
+        let buf_len = 3 * CHUNK_SIZE / 2;
+        let len = 4 * CHUNK_SIZE as u64 + 10;
+        let mut sent = 0;
+        let mut chunks = Vec::new();
+        while sent < len {
+            let (offset, n) = next_chunk(sent, len, buf_len);
+            assert!(n > 0 && offset + n <= buf_len);
+            chunks.push((offset, n));
+            sent += n as u64;
+        }
+        let half = CHUNK_SIZE / 2;
+        assert_eq!(
+            chunks,
+            [
+                (0, CHUNK_SIZE),
+                // Stops at the end of the buffer, then wraps around.
+                (CHUNK_SIZE, half),
+                (0, CHUNK_SIZE),
+                (CHUNK_SIZE, half),
+                (0, CHUNK_SIZE),
+                // The short last chunk.
+                (CHUNK_SIZE, 10),
+            ]
+        );
+        assert_eq!(next_chunk(5, 5, buf_len), (5, 0));
+        assert_eq!(next_chunk(0, 3, 16), (0, 3));
+    }
+
+    #[test]
+    fn chunk_heads() {
+        let head = |len| chunk_head(len).as_slice().to_vec();
+        assert_eq!(head(0), b"0\r\n");
+        assert_eq!(head(10), b"a\r\n");
+        assert_eq!(head(CHUNK_SIZE), b"100000\r\n");
+        assert_eq!(head(usize::MAX), b"ffffffffffffffff\r\n");
+    }
+}
@@ -0,0 +1,432 @@
+use std::fmt;
+use std::time::Duration;
+
+use bench_payload::{http, parse_duration, parse_size};
+
+use crate::duplex::Duplex;
+use crate::payload::{self, Content, SendPath};
+
+/// What a request asks the server to do, decided from its target.
+#[derive(Debug)]
+pub enum Route {
+    Download(Download),
+    /// Discard the request body and report how fast it arrived.
+    Upload,
+    /// Upgrade to a full-duplex stream, see [`crate::duplex`].
+    Duplex(Duplex),
+    /// A few bytes for request/response latency.
+    Ping,
+}
+
+#[derive(Debug, Clone)]
+pub struct Download {
+    pub size: u64,
+    pub framing: Framing,
+    pub send_path: SendPath,
+    pub content: Content,
+    /// Send the body's XXH64 in a trailer, see [`bench_payload::checksum`].
+    pub checksum: bool,
+    /// Stop after this long, counted from the first body byte, even if fewer
+    /// than `size` bytes went out. The body is chunked since its length is
+    /// not known up front.
+    pub duration: Option<Duration>,
+}
+
+impl Download {
+    /// Reframes a chunked body for an HTTP/1.0 client: a known length goes
+    /// out after a `Content-Length`, a body with a deadline until the
+    /// connection closes. There is no trailer to carry the checksum.
+    pub fn http10(&mut self) {
+        if self.framing == Framing::Chunked {
+            self.framing = match self.duration {
+                Some(_) => Framing::Close,
+                None => Framing::Length,
+            };
+        }
+        self.checksum = false;
+    }
+}
+
+/// How the response body is delimited on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// `Transfer-Encoding: chunked` with 1 MiB chunks.
+    Chunked,
+    /// `Content-Length` followed by the raw body.
+    Length,
+    /// The raw body, ended by closing the connection. Only for HTTP/1.0
+    /// clients, which know neither chunked bodies nor trailers.
+    Close,
+}
+
+impl Framing {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "chunked" => Some(Framing::Chunked),
+            "length" | "content-length" => Some(Framing::Length),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RouteError {
+    NotFound,
+    MethodNotAllowed,
+    BadParam(&'static str, String),
+}
+
+impl RouteError {
+    pub fn status(&self) -> (u16, &'static str) {
+        match self {
+            RouteError::NotFound => (404, "Not Found"),
+            RouteError::MethodNotAllowed => (405, "Method Not Allowed"),
+            RouteError::BadParam(..) => (400, "Bad Request"),
+        }
+    }
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::NotFound => write!(f, "no such endpoint"),
+            RouteError::MethodNotAllowed => write!(f, "method not allowed"),
+            RouteError::BadParam(name, value) => write!(f, "invalid {name}: {value:?}"),
+        }
+    }
+}
+
+/// Routes `GET /bench?size=4GiB`, `GET /bytes/1048576`, `GET /`, `GET /file`,
+/// `GET /ping`, `POST /upload` and `GET /duplex?size=1GiB&upload=1GiB`. Anything a download
+/// does not specify, such as `framing=length`, `send=zerocopy`,
+/// `content=random&seed=7` or `checksum=xxh64`, is taken from `defaults`.
+/// `duration=30s` streams until the deadline, or until the client disconnects,
+/// with no size limit unless one is given.
+pub fn route(method: &str, target: &str, defaults: &Download) -> Result<Route, RouteError> {
+    let target = Target::parse(target);
+
+    if target.path == "/upload" {
+        return match method {
+            "POST" | "PUT" => Ok(Route::Upload),
+            _ => Err(RouteError::MethodNotAllowed),
+        };
+    }
+    if !matches!(method, "GET" | "HEAD") {
+        return Err(RouteError::MethodNotAllowed);
+    }
+
+    if target.path == "/ping" {
+        return Ok(Route::Ping);
+    }
+
+    if target.path == "/duplex" {
+        let download = match target.query("size") {
+            Some(size) => parse_size(&size).ok_or(RouteError::BadParam("size", size))?,
+            None => defaults.size,
+        };
+        let upload = match target.query("upload") {
+            Some(size) => parse_size(&size).ok_or(RouteError::BadParam("upload", size))?,
+            None => download,
+        };
+        let content = content(&target, defaults.content)?;
+        return Ok(Route::Duplex(Duplex {
+            download,
+            upload,
+            content,
+        }));
+    }
+
+    let mut download = defaults.clone();
+
+    match target.path {
+        "/" | "/bench" => {
+            if let Some(size) = target.query("size") {
+                download.size = parse_size(&size).ok_or(RouteError::BadParam("size", size))?;
+            } else if target.query("duration").is_some() {
+                download.size = u64::MAX;
+            }
+        }
+        "/file" => {
+            download.size = payload::payload_file_len().ok_or(RouteError::NotFound)?;
+            download.send_path = SendPath::Sendfile;
+            download.content = Content::File;
+        }
+        path => {
+            let size = path.strip_prefix("/bytes/").ok_or(RouteError::NotFound)?;
+            let size = percent_decode(size);
+            download.size = parse_size(&size).ok_or(RouteError::BadParam("size", size))?;
+        }
+    }
+
+    if let Some(framing) = target.query("framing") {
+        download.framing =
+            Framing::parse(&framing).ok_or(RouteError::BadParam("framing", framing))?;
+    }
+
+    if let Some(duration) = target.query("duration") {
+        download.duration = Some(
+            parse_duration(&duration)
+                .filter(|d| !d.is_zero())
+                .ok_or(RouteError::BadParam("duration", duration))?,
+        );
+        if download.framing == Framing::Length {
+            if target.query("framing").is_some() {
+                return Err(RouteError::BadParam(
+                    "duration",
+                    "a deadline needs framing=chunked".into(),
+                ));
+            }
+            download.framing = Framing::Chunked;
+        }
+    }
+
+    if let Some(path) = target.query("send") {
+        download.send_path = SendPath::parse(&path).ok_or(RouteError::BadParam("send", path))?;
+    }
+
+    download.content = content(&target, download.content)?;
+
+    if let Some(checksum) = target.query("checksum") {
+        download.checksum = match checksum.to_ascii_lowercase().as_str() {
+            "1" | "true" | "xxh64" => true,
+            "0" | "false" | "none" => false,
+            _ => return Err(RouteError::BadParam("checksum", checksum)),
+        };
+    }
+    if download.checksum && download.framing != Framing::Chunked {
+        return Err(RouteError::BadParam(
+            "checksum",
+            "a trailer needs framing=chunked".into(),
+        ));
+    }
+
+    Ok(Route::Download(download))
+}
+
+/// `content=zeros|random|pattern|file` plus `seed=` for `random`. A seed on
+/// its own implies `random`.
+fn content(target: &Target, default: Content) -> Result<Content, RouteError> {
+    let mut content = match target.query("content") {
+        Some(name) => Content::parse(&name).ok_or(RouteError::BadParam("content", name))?,
+        None => default,
+    };
+    if let Some(seed) = target.query("seed") {
+        let explicit = target.query("content").is_some();
+        content = match (seed.parse(), content) {
+            (Ok(seed), Content::Random(_)) => Content::Random(seed),
+            (Ok(seed), _) if !explicit => Content::Random(seed),
+            _ => return Err(RouteError::BadParam("seed", seed)),
+        };
+    }
+    if content == Content::File && payload::payload_file_len().is_none() {
+        return Err(RouteError::BadParam(
+            "content",
+            "file without PAYLOAD_FILE".into(),
+        ));
+    }
+    Ok(content)
+}
+
+pub struct Target<'a> {
+    pub path: &'a str,
+    query: &'a str,
+}
+
+impl<'a> Target<'a> {
+    /// Splits an origin-form or absolute-form request target.
+    pub fn parse(target: &'a str) -> Self {
+        let target = match target.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("/", |i| &rest[i..]),
+            None => target,
+        };
+        let target = target.split_once('#').map_or(target, |(t, _)| t);
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        Self { path, query }
+    }
+
+    /// Decoded value of the first `key` parameter in the query string.
+    pub fn query(&self, key: &str) -> Option<String> {
+        self.query
+            .split('&')
+            .filter_map(|pair| {
+                let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(k) == key).then(|| percent_decode(v))
+            })
+            .next()
+    }
+}
+
+/// Query-string decoding, where `+` is a space.
+fn percent_decode(s: &str) -> String {
+    http::percent_decode(&s.replace('+', " "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULTS: Download = Download {
+        size: 1 << 20,
+        framing: Framing::Chunked,
+        send_path: SendPath::Plain,
+        content: Content::Zeros,
+        checksum: false,
+        duration: None,
+    };
+
+    fn download(target: &str) -> Download {
+        match route("GET", target, &DEFAULTS) {
+            Ok(Route::Download(download)) => download,
+            other => panic!("{target}: {other:?}"),
+        }
+    }
+
+    fn bad_param(target: &str) -> &'static str {
+        match route("GET", target, &DEFAULTS) {
+            Err(RouteError::BadParam(name, _)) => name,
+            other => panic!("{target}: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(download("/bench").size, 1 << 20);
+        assert_eq!(download("/bench?size=4%20GiB").size, 4 << 30);
+        assert_eq!(download("/bench?size=4+GiB").size, 4 << 30);
+        assert_eq!(download("/bytes/1.5GB").size, 1_500_000_000);
+        assert_eq!(download("/bytes/4%20KiB").size, 4096);
+        // The path wins over the query.
+        assert_eq!(download("/bytes/100?size=5").size, 100);
+        assert_eq!(download("http://example.com/bytes/7?x#frag").size, 7);
+
+        assert_eq!(bad_param("/bench?size="), "size");
+        assert_eq!(bad_param("/bench?size=4XB"), "size");
+        assert_eq!(bad_param("/bytes/"), "size");
+        assert!(matches!(
+            route("GET", "/nope", &DEFAULTS),
+            Err(RouteError::NotFound)
+        ));
+        assert!(matches!(
+            route("POST", "/bench", &DEFAULTS),
+            Err(RouteError::MethodNotAllowed)
+        ));
+    }
+
+    #[test]
+    fn durations() {
+        let d = download("/bench?duration=1.5s");
+        assert_eq!(d.duration, Some(Duration::from_millis(1500)));
+        assert_eq!(d.size, u64::MAX);
+        assert_eq!(d.framing, Framing::Chunked);
+        assert_eq!(download("/bench?duration=2m&size=1MiB").size, 1 << 20);
+        assert_eq!(
+            download("/bytes/10?duration=500ms").duration,
+            Some(Duration::from_millis(500))
+        );
+
+        // A default of `length` gives way, an explicit one is an error.
+        let defaults = Download {
+            framing: Framing::Length,
+            ..DEFAULTS
+        };
+        match route("GET", "/bench?duration=1s", &defaults) {
+            Ok(Route::Download(d)) => assert_eq!(d.framing, Framing::Chunked),
+            other => panic!("{other:?}"),
+        }
+        assert_eq!(bad_param("/bench?duration=1s&framing=length"), "duration");
+
+        for target in [
+            "/bench?duration=0s",
+            "/bench?duration=-1s",
+            "/bench?duration=5x",
+        ] {
+            assert_eq!(bad_param(target), "duration");
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        assert!(download("/bench?checksum=xxh64").checksum);
+        assert!(download("/bench?checksum=TRUE&framing=chunked").checksum);
+        assert!(!download("/bench?checksum=none").checksum);
+        // Turning it off is fine with any framing.
+        assert!(!download("/bench?checksum=0&framing=length").checksum);
+        assert_eq!(bad_param("/bench?checksum=crc32"), "checksum");
+        assert_eq!(bad_param("/bench?checksum=1&framing=length"), "checksum");
+
+        let defaults = Download {
+            framing: Framing::Length,
+            checksum: true,
+            ..DEFAULTS
+        };
+        assert!(matches!(
+            route("GET", "/bench", &defaults),
+            Err(RouteError::BadParam("checksum", _))
+        ));
+        // A deadline switches to chunked, which carries the trailer again.
+        assert!(route("GET", "/bench?duration=1s", &defaults).is_ok());
+    }
+
+    #[test]
+    fn contents() {
+        assert_eq!(download("/bench").content, Content::Zeros);
+        assert_eq!(download("/bench?content=pattern").content, Content::Pattern);
+        assert_eq!(
+            download("/bench?content=random").content,
+            Content::Random(bench_payload::DEFAULT_SEED)
+        );
+        assert_eq!(download("/bench?seed=7").content, Content::Random(7));
+        assert_eq!(
+            download("/bench?content=random&seed=7").content,
+            Content::Random(7)
+        );
+        // A seed replaces the default content, but not one asked for.
+        let defaults = Download {
+            content: Content::Pattern,
+            ..DEFAULTS
+        };
+        match route("GET", "/bench?seed=3", &defaults) {
+            Ok(Route::Download(d)) => assert_eq!(d.content, Content::Random(3)),
+            other => panic!("{other:?}"),
+        }
+        assert_eq!(bad_param("/bench?content=pattern&seed=7"), "seed");
+        assert_eq!(bad_param("/bench?seed=-1"), "seed");
+        assert_eq!(bad_param("/bench?content=noise"), "content");
+        // No PAYLOAD_FILE in tests.
+        assert_eq!(bad_param("/bench?content=file"), "content");
+        assert!(matches!(
+            route("GET", "/file", &DEFAULTS),
+            Err(RouteError::NotFound)
+        ));
+
+        match route("GET", "/duplex?size=1KiB&seed=9", &DEFAULTS) {
+            Ok(Route::Duplex(duplex)) => {
+                assert_eq!((duplex.download, duplex.upload), (1024, 1024));
+                assert_eq!(duplex.content, Content::Random(9));
+            }
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn query() {
+        let target = Target::parse("/bench?size=1&a%20b=c%2Bd&size=2&flag");
+        assert_eq!(target.path, "/bench");
+        assert_eq!(target.query("size").as_deref(), Some("1"));
+        assert_eq!(target.query("a b").as_deref(), Some("c+d"));
+        assert_eq!(target.query("flag").as_deref(), Some(""));
+        assert_eq!(target.query("missing"), None);
+        assert_eq!(Target::parse("http://host").path, "/");
+    }
+
+    #[test]
+    fn percent() {
+        assert_eq!(percent_decode("4%20GiB"), "4 GiB");
+        assert_eq!(percent_decode("%41%62c"), "Abc");
+        assert_eq!(percent_decode("1+2"), "1 2");
+        assert_eq!(percent_decode("%zz"), "%zz");
+        assert_eq!(percent_decode("a%4"), "a%4");
+        assert_eq!(percent_decode("a%"), "a%");
+        assert_eq!(percent_decode("%ff"), "\u{fffd}");
+    }
+}
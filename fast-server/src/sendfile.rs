@@ -0,0 +1,239 @@
+//! Send path that serves body bytes from a file or memfd with sendfile(2), so
+//! the payload never passes through userspace.
+
+use std::{
+    fs::File,
+    io::{self, Write as _},
+    os::fd::{AsRawFd as _, FromRawFd as _, RawFd},
+    path::Path,
+    sync::OnceLock,
+    time::Instant,
+};
+
+use compio::driver::{
+    ToSharedFd as _,
+    op::{Interest, PollOnce},
+};
+use compio::net::TcpStream;
+
+use crate::payload::{self, CHUNK_SIZE, Content};
+use crate::route::Framing;
+
+static SOURCE: OnceLock<Source> = OnceLock::new();
+
+pub struct Source {
+    file: File,
+    len: u64,
+    /// Whether the bytes come from `PAYLOAD_FILE` rather than a memfd of zeros.
+    is_file: bool,
+}
+
+impl Source {
+    /// Whether bodies sent from this source are `content`.
+    pub fn holds(&self, content: Content) -> bool {
+        match content {
+            Content::File => self.is_file,
+            Content::Zeros => !self.is_file,
+            Content::Random(_) | Content::Pattern => false,
+        }
+    }
+}
+
+/// Opens `path` as the payload source, or fills a memfd with zeros when no
+/// file is configured. Only the first call has an effect.
+pub fn init(path: Option<&Path>) -> io::Result<&'static Source> {
+    if let Some(source) = SOURCE.get() {
+        return Ok(source);
+    }
+    let source = match path {
+        Some(path) => {
+            let file = File::open(path)?;
+            let len = file.metadata()?.len();
+            if len == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is empty", path.display()),
+                ));
+            }
+            Source {
+                file,
+                len,
+                is_file: true,
+            }
+        }
+        None => {
+            let fd =
+                unsafe { libc::memfd_create(c"fast-server-payload".as_ptr(), libc::MFD_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: `fd` was just created and is owned by nobody else.
+            let mut file = unsafe { File::from_raw_fd(fd) };
+            file.write_all(&vec![0; CHUNK_SIZE])?;
+            Source {
+                file,
+                len: CHUNK_SIZE as u64,
+                is_file: false,
+            }
+        }
+    };
+    Ok(SOURCE.get_or_init(|| source))
+}
+
+pub fn source() -> Option<&'static Source> {
+    SOURCE.get()
+}
+
+/// Sends a `len` byte body on `stream`, except for the last chunk, wrapping
+/// around to the start of the source when `len` exceeds it. The size lines and
+/// CRLFs go out with `MSG_MORE`, so they share segments with the file data.
+/// Stops at the first chunk boundary past `deadline` and returns the body
+/// bytes sent.
+pub async fn send_body(
+    stream: &TcpStream,
+    len: u64,
+    framing: Framing,
+    deadline: Option<Instant>,
+) -> io::Result<u64> {
+    let source = source().ok_or_else(|| io::Error::other("no sendfile source"))?;
+    let chunked = framing == Framing::Chunked;
+    // A full socket buffer has to park the task, not the worker.
+    let _nonblocking = NonBlocking::set(stream.as_raw_fd())?;
+
+    let mut sent = 0;
+    while payload::more(sent, len, deadline) {
+        let n = (len - sent).min(CHUNK_SIZE as u64);
+        if chunked {
+            send_more(stream, &payload::chunk_head(n as usize)).await?;
+        }
+        let mut done = 0;
+        while done < n {
+            let offset = (sent + done) % source.len;
+            let count = (n - done).min(source.len - offset);
+            done += sendfile(stream, &source.file, offset, count).await?;
+        }
+        if chunked {
+            send_more(stream, b"\r\n").await?;
+        }
+        sent += n;
+    }
+    Ok(sent)
+}
+
+async fn sendfile(stream: &TcpStream, file: &File, offset: u64, count: u64) -> io::Result<u64> {
+    let mut offset = offset as libc::off_t;
+    let n = retry(stream, || unsafe {
+        libc::sendfile(
+            stream.as_raw_fd(),
+            file.as_raw_fd(),
+            &mut offset,
+            count as usize,
+        )
+    })
+    .await?;
+    Ok(n as u64)
+}
+
+/// Sends `data`, telling the kernel more follows so it is held back until
+/// the next write.
+async fn send_more(stream: &TcpStream, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        let n = retry(stream, || unsafe {
+            libc::send(
+                stream.as_raw_fd(),
+                data.as_ptr().cast(),
+                data.len(),
+                libc::MSG_NOSIGNAL | libc::MSG_MORE,
+            )
+        })
+        .await?;
+        data = &data[n..];
+    }
+    Ok(())
+}
+
+/// Runs the syscall `op` until it makes progress: retries on `EINTR`, waits
+/// on the worker's runtime on `EAGAIN`, and returns any other error.
+async fn retry(stream: &TcpStream, mut op: impl FnMut() -> isize) -> io::Result<usize> {
+    loop {
+        match op() {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            n if n > 0 => return Ok(n as usize),
+            _ => {}
+        }
+        let err = io::Error::last_os_error();
+        match err.kind() {
+            io::ErrorKind::Interrupted => {}
+            io::ErrorKind::WouldBlock => {
+                let poll = PollOnce::new(stream.to_shared_fd(), Interest::Writable);
+                compio::runtime::submit(poll).await.0?;
+            }
+            _ => return Err(err),
+        }
+    }
+}
+
+/// `O_NONBLOCK` on a socket for as long as this lives. The flag is shared
+/// with compio's own fd, so it is cleared again on drop unless it was set
+/// before, and later requests on the connection see the socket as it was.
+struct NonBlocking {
+    sock: RawFd,
+    /// Flags to restore, if they had to change.
+    restore: Option<libc::c_int>,
+}
+
+impl NonBlocking {
+    fn set(sock: RawFd) -> io::Result<Self> {
+        let flags = unsafe { libc::fcntl(sock, libc::F_GETFL) };
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if flags & libc::O_NONBLOCK != 0 {
+            return Ok(Self {
+                sock,
+                restore: None,
+            });
+        }
+        if unsafe { libc::fcntl(sock, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            sock,
+            restore: Some(flags),
+        })
+    }
+}
+
+impl Drop for NonBlocking {
+    fn drop(&mut self) {
+        if let Some(flags) = self.restore {
+            unsafe { libc::fcntl(self.sock, libc::F_SETFL, flags) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+
+    fn nonblocking(sock: &UnixStream) -> bool {
+        let flags = unsafe { libc::fcntl(sock.as_raw_fd(), libc::F_GETFL) };
+        flags & libc::O_NONBLOCK != 0
+    }
+
+    #[test]
+    fn nonblocking_is_restored() {
+        let (a, b) = UnixStream::pair().unwrap();
+        {
+            let _guard = NonBlocking::set(a.as_raw_fd()).unwrap();
+            assert!(nonblocking(&a));
+        }
+        assert!(!nonblocking(&a));
+
+        b.set_nonblocking(true).unwrap();
+        drop(NonBlocking::set(b.as_raw_fd()).unwrap());
+        assert!(nonblocking(&b));
+    }
+}
@@ -0,0 +1,405 @@
+//! Optional send path that drives its own io_uring with the payload content
+//! registered as a fixed buffer, sending with `IORING_OP_SEND_ZC` where the
+//! kernel has it and `IORING_OP_WRITE_FIXED` otherwise.
+//!
+//! compio completes an operation on its first CQE, but a zero-copy send posts
+//! a second notification CQE later, so these sends cannot go through the
+//! compio driver. Instead a download borrows one of its worker's rings for the
+//! whole body and waits for completions by polling the ring's fd through
+//! compio, so the connection's task never leaves the worker thread.
+
+use std::{
+    cell::RefCell,
+    io,
+    os::fd::{AsRawFd as _, BorrowedFd, OwnedFd, RawFd},
+    rc::Rc,
+    sync::Arc,
+    sync::OnceLock,
+    time::Instant,
+};
+
+use compio::driver::op::{Interest, PollOnce};
+use compio::net::TcpStream;
+use io_uring::{IoUring, Probe, cqueue, opcode, squeue, types::Fd};
+
+use crate::payload::{self, ChunkHead, SendPath};
+use crate::route::Framing;
+
+const RING_ENTRIES: u32 = 8;
+
+/// The CRLF ending the previous chunk and the next chunk's size line.
+const HEAD_LEN: usize = 2 + size_of::<ChunkHead>();
+
+thread_local! {
+    /// Idle rings of this worker. Each download has a ring to itself, so
+    /// completions never need to be routed between connections.
+    static RINGS: RefCell<Vec<Ring>> = const { RefCell::new(Vec::new()) };
+}
+
+static SUPPORTED: OnceLock<SendPath> = OnceLock::new();
+
+/// Fastest path this kernel supports, probed once.
+pub fn supported() -> SendPath {
+    *SUPPORTED.get_or_init(|| probe().unwrap_or(SendPath::Plain))
+}
+
+fn probe() -> io::Result<SendPath> {
+    let mut ring = Ring::new()?;
+    ring.register(&payload::buffer(payload::Content::Zeros))?;
+    let mut probe = Probe::new();
+    ring.uring.submitter().register_probe(&mut probe)?;
+    Ok(if probe.is_supported(opcode::SendZc::CODE) {
+        SendPath::ZeroCopy
+    } else if probe.is_supported(opcode::WriteFixed::CODE) {
+        SendPath::Fixed
+    } else {
+        SendPath::Plain
+    })
+}
+
+/// Sends a `len` byte body repeating `buf` on `stream`, except for the last
+/// chunk. Stops at the first chunk boundary past `deadline` and returns the
+/// body bytes sent.
+pub async fn send_body(
+    stream: &TcpStream,
+    len: u64,
+    framing: Framing,
+    deadline: Option<Instant>,
+    path: SendPath,
+    buf: Arc<[u8]>,
+) -> io::Result<u64> {
+    let mut ring = match RINGS.with_borrow_mut(Vec::pop) {
+        Some(ring) => ring,
+        None => Ring::new()?,
+    };
+    ring.register(&buf)?;
+    let result = ring
+        .send_body(stream.as_raw_fd(), len, framing, deadline, path)
+        .await;
+    // Settle even on error so the ring goes back clean.
+    ring.settle().await?;
+    RINGS.with_borrow_mut(|rings| rings.push(ring));
+    result
+}
+
+/// One send of a chunk.
+#[derive(Debug, Clone, Copy)]
+enum Piece {
+    /// [`Ring::head`].
+    Head,
+    /// `len` bytes of the registered buffer starting at `offset`.
+    Body { offset: usize, len: usize },
+    /// The CRLF after the last chunk's data.
+    Tail,
+}
+
+struct Ring {
+    uring: IoUring,
+    /// A duplicate of the ring's fd that compio polls for completions.
+    fd: Rc<OwnedFd>,
+    /// Payload content registered as fixed buffer 0. Content buffers are
+    /// shared and never written, and holding a reference keeps it alive.
+    buf: Option<Arc<[u8]>>,
+    /// Size line of the chunk being sent, after the CRLF ending the one
+    /// before. The kernel reads it from here.
+    head: [u8; HEAD_LEN],
+    head_len: usize,
+    /// Sends pushed whose CQE has not arrived yet, more than `complete`
+    /// waited for if it failed halfway.
+    in_flight: usize,
+    /// Zero-copy sends whose notification CQE has not arrived yet.
+    pending_notifs: usize,
+}
+
+impl Ring {
+    fn new() -> io::Result<Self> {
+        let uring = IoUring::new(RING_ENTRIES)?;
+        // SAFETY: `uring` owns its fd for the duration of the borrow.
+        let fd = unsafe { BorrowedFd::borrow_raw(uring.as_raw_fd()) }.try_clone_to_owned()?;
+        Ok(Self {
+            fd: Rc::new(fd),
+            uring,
+            buf: None,
+            head: [0; HEAD_LEN],
+            head_len: 0,
+            in_flight: 0,
+            pending_notifs: 0,
+        })
+    }
+
+    /// Makes `buf` fixed buffer 0 unless it already is. The shared content
+    /// buffers stay registered across bodies; a one-off random seed costs a
+    /// registration per body.
+    fn register(&mut self, buf: &Arc<[u8]>) -> io::Result<()> {
+        if self.buf.as_ref().is_some_and(|b| Arc::ptr_eq(b, buf)) {
+            return Ok(());
+        }
+        let submitter = self.uring.submitter();
+        if self.buf.take().is_some() {
+            submitter.unregister_buffers()?;
+        }
+        let iovec = libc::iovec {
+            iov_base: buf.as_ptr() as *mut _,
+            iov_len: buf.len(),
+        };
+        // SAFETY: `self.buf` keeps `buf` alive until it is unregistered, and
+        // content buffers are never written to.
+        unsafe { submitter.register_buffers(&[iovec])? };
+        self.buf = Some(buf.clone());
+        Ok(())
+    }
+
+    async fn send_body(
+        &mut self,
+        fd: RawFd,
+        len: u64,
+        framing: Framing,
+        deadline: Option<Instant>,
+        path: SendPath,
+    ) -> io::Result<u64> {
+        let buf_len = self.buf.as_ref().map_or(0, |buf| buf.len());
+        let mut sent = 0;
+        while payload::more(sent, len, deadline) {
+            let (offset, n) = payload::next_chunk(sent, len, buf_len);
+            let body = Piece::Body { offset, len: n };
+            match framing {
+                Framing::Length | Framing::Close => self.send_chunk(fd, &[body], path).await?,
+                Framing::Chunked => {
+                    self.set_head(sent > 0, n);
+                    self.send_chunk(fd, &[Piece::Head, body], path).await?;
+                }
+            }
+            sent += n as u64;
+        }
+        if framing == Framing::Chunked && sent > 0 {
+            self.send_chunk(fd, &[Piece::Tail], path).await?;
+        }
+        Ok(sent)
+    }
+
+    fn set_head(&mut self, after_chunk: bool, len: usize) {
+        let start = if after_chunk {
+            self.head[..2].copy_from_slice(b"\r\n");
+            2
+        } else {
+            0
+        };
+        let line = payload::chunk_head(len);
+        self.head[start..start + line.len()].copy_from_slice(&line);
+        self.head_len = start + line.len();
+    }
+
+    /// Sends `pieces` as one chain of linked SQEs in a single submission.
+    /// Every piece but the last waits for all of its bytes, so a short send
+    /// can only end the chain early; the pieces it cancelled then go out one
+    /// at a time.
+    async fn send_chunk(&mut self, fd: RawFd, pieces: &[Piece], path: SendPath) -> io::Result<()> {
+        for (i, &piece) in pieces.iter().enumerate() {
+            let mut entry = self.entry(fd, piece, 0, path)?.user_data(i as u64);
+            if i + 1 < pieces.len() {
+                entry = entry.flags(squeue::Flags::IO_LINK);
+            }
+            self.push(&entry)?;
+        }
+        let mut results = [0; 2];
+        let results = &mut results[..pieces.len()];
+        self.complete(results).await?;
+
+        for (&piece, &result) in pieces.iter().zip(results.iter()) {
+            let mut done = match result {
+                n if n == -libc::ECANCELED => 0,
+                n => sent(n)?,
+            };
+            while done < self.len(piece) {
+                let entry = self.entry(fd, piece, done, path)?.user_data(0);
+                self.push(&entry)?;
+                let mut result = [0];
+                self.complete(&mut result).await?;
+                done += sent(result[0])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn len(&self, piece: Piece) -> usize {
+        match piece {
+            Piece::Head => self.head_len,
+            Piece::Body { len, .. } => len,
+            Piece::Tail => 2,
+        }
+    }
+
+    /// The SQE sending what is left of `piece` after its first `done` bytes.
+    fn entry(
+        &self,
+        fd: RawFd,
+        piece: Piece,
+        done: usize,
+        path: SendPath,
+    ) -> io::Result<squeue::Entry> {
+        let copy = |data: &[u8]| {
+            let rest = &data[done..];
+            opcode::Send::new(Fd(fd), rest.as_ptr(), rest.len() as u32)
+                .flags(libc::MSG_MORE | libc::MSG_WAITALL)
+                .build()
+        };
+        Ok(match piece {
+            Piece::Head => copy(&self.head[..self.head_len]),
+            Piece::Tail => copy(b"\r\n"),
+            Piece::Body { offset, len } => {
+                let buf = self
+                    .buf
+                    .as_ref()
+                    .ok_or_else(|| io::Error::other("no registered buffer"))?;
+                let ptr = buf[offset + done..].as_ptr();
+                let n = (len - done) as u32;
+                match path {
+                    SendPath::ZeroCopy => opcode::SendZc::new(Fd(fd), ptr, n)
+                        .buf_index(Some(0))
+                        .flags(libc::MSG_WAITALL)
+                        .build(),
+                    SendPath::Fixed => opcode::WriteFixed::new(Fd(fd), ptr, n, 0).build(),
+                    SendPath::Plain | SendPath::Sendfile => opcode::Send::new(Fd(fd), ptr, n)
+                        .flags(libc::MSG_WAITALL)
+                        .build(),
+                }
+            }
+        })
+    }
+
+    fn push(&mut self, entry: &squeue::Entry) -> io::Result<()> {
+        // SAFETY: the buffers `entry` points to live in `self` or are static,
+        // and `complete` does not return before the send has completed.
+        unsafe { self.uring.submission().push(entry) }
+            .map_err(|_| io::Error::other("io_uring submission queue full"))?;
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Submits the queued sends and waits until each has completed, storing
+    /// the result of the one with user data `i` in `results[i]`.
+    async fn complete(&mut self, results: &mut [i32]) -> io::Result<()> {
+        self.submit()?;
+        let mut left = results.len();
+        loop {
+            left -= self.reap(results)?;
+            if left == 0 {
+                return Ok(());
+            }
+            self.readable().await?;
+        }
+    }
+
+    /// Waits for every send and zero-copy notification still outstanding,
+    /// whose results nobody needs any more, so the ring can be reused.
+    async fn settle(&mut self) -> io::Result<()> {
+        loop {
+            self.reap(&mut [])?;
+            if self.in_flight == 0 && self.pending_notifs == 0 {
+                return Ok(());
+            }
+            self.readable().await?;
+        }
+    }
+
+    fn submit(&mut self) -> io::Result<()> {
+        loop {
+            match self.uring.submit() {
+                Ok(_) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Takes every CQE there is, storing send results in `results` and
+    /// keeping count of zero-copy notifications. Results of sends left over
+    /// from a failed `complete` fall outside `results` and are dropped.
+    /// Returns how many results were stored.
+    fn reap(&mut self, results: &mut [i32]) -> io::Result<usize> {
+        // Late notifications can overflow the small CQ. The ring's fd then
+        // polls readable, but the CQEs only show up after an enter.
+        if self.uring.submission().cq_overflow() {
+            self.submit()?;
+        }
+        let mut arrived = 0;
+        for cqe in self.uring.completion() {
+            if cqueue::notif(cqe.flags()) {
+                self.pending_notifs = self.pending_notifs.saturating_sub(1);
+                continue;
+            }
+            if cqueue::more(cqe.flags()) {
+                self.pending_notifs += 1;
+            }
+            self.in_flight = self.in_flight.saturating_sub(1);
+            if let Some(result) = results.get_mut(cqe.user_data() as usize) {
+                *result = cqe.result();
+                arrived += 1;
+            }
+        }
+        Ok(arrived)
+    }
+
+    /// Waits on the worker's runtime until the ring has a CQE.
+    async fn readable(&self) -> io::Result<()> {
+        let poll = PollOnce::new(self.fd.clone(), Interest::Readable);
+        compio::runtime::submit(poll).await.0?;
+        Ok(())
+    }
+}
+
+/// Bytes sent by a send that completed with `result`, which is never zero.
+fn sent(result: i32) -> io::Result<usize> {
+    match result {
+        0 => Err(io::ErrorKind::WriteZero.into()),
+        n if n < 0 => Err(io::Error::from_raw_os_error(-n)),
+        n => Ok(n as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nop(ring: &mut Ring, user_data: u64) {
+        ring.push(&opcode::Nop::new().build().user_data(user_data))
+            .unwrap();
+    }
+
+    #[test]
+    fn reap_without_results_drops_send_cqes() {
+        let mut ring = Ring::new().unwrap();
+        nop(&mut ring, 1);
+        ring.uring.submit_and_wait(1).unwrap();
+        // What `settle` does after a failed `complete` left a send behind.
+        assert_eq!(ring.reap(&mut []).unwrap(), 0);
+        assert_eq!(ring.in_flight, 0);
+    }
+
+    #[test]
+    fn complete_ignores_stale_results() {
+        compio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut ring = Ring::new().unwrap();
+            nop(&mut ring, 1);
+            nop(&mut ring, 0);
+            let mut results = [-1];
+            ring.complete(&mut results).await.unwrap();
+            assert_eq!(results, [0]);
+            ring.settle().await.unwrap();
+            assert_eq!(ring.in_flight, 0);
+        });
+    }
+
+    #[test]
+    fn settle_waits_for_outstanding_sends() {
+        compio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut ring = Ring::new().unwrap();
+            nop(&mut ring, 0);
+            nop(&mut ring, 1);
+            ring.submit().unwrap();
+            ring.settle().await.unwrap();
+            assert_eq!(ring.in_flight, 0);
+            assert!(ring.uring.completion().is_empty());
+        });
+    }
+}
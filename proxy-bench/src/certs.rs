@@ -0,0 +1,63 @@
+//! Self-signed certificates for the TLS-based protocols, made with the
+//! `openssl` command.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Context as _;
+
+use crate::protocol::KeyType;
+
+/// Name the certificate is issued for and clients ask for with SNI.
+pub const SERVER_NAME: &str = "bench.local";
+
+pub struct Cert {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Writes a key of type `key` and a certificate for [`SERVER_NAME`] to `dir`.
+pub fn generate(dir: &Path, key: KeyType) -> anyhow::Result<Cert> {
+    let cert = Cert {
+        cert: dir.join(format!("server-{}.crt", key.name())),
+        key: dir.join(format!("server-{}.key", key.name())),
+    };
+    match key {
+        KeyType::Rsa4096 => openssl(&["genrsa", "-out"], &cert.key, &["4096"])?,
+        KeyType::Ed25519 => openssl(
+            &["genpkey", "-algorithm", "ed25519", "-out"],
+            &cert.key,
+            &[],
+        )?,
+    }
+    let subject = format!("/CN={SERVER_NAME}/O=benchmark/C=HK");
+    let mut req = Command::new("openssl");
+    req.args(["req", "-x509", "-new", "-nodes", "-days", "3650", "-key"])
+        .arg(&cert.key)
+        .args(["-out"])
+        .arg(&cert.cert)
+        .args(["-subj", &subject]);
+    if key == KeyType::Rsa4096 {
+        req.arg("-sha256");
+    }
+    run(req).with_context(|| format!("{} certificate", key.name()))?;
+    Ok(cert)
+}
+
+fn openssl(args: &[&str], path: &Path, rest: &[&str]) -> anyhow::Result<()> {
+    let mut cmd = Command::new("openssl");
+    cmd.args(args).arg(path).args(rest);
+    run(cmd).with_context(|| format!("{}", path.display()))
+}
+
+fn run(mut cmd: Command) -> anyhow::Result<()> {
+    let output = cmd.output().context("run openssl")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "openssl {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,584 @@
+mod certs;
+mod matrix;
+mod ports;
+mod process;
+mod protocol;
+mod proxies;
+mod ready;
+mod supervisor;
+mod workload;
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+use anyhow::Context as _;
+
+use crate::certs::Cert;
+use crate::process::{Exit, Exits, Process};
+use crate::protocol::{Case, KeyType, Secrets};
+use crate::proxies::Setup;
+use crate::workload::{Report, Workload};
+use bench_payload::json::{ToJson as _, Value, object};
+use bench_payload::stats::Summary;
+
+const USAGE: &str = "\
+usage: proxy-bench [OPTIONS] [FILTER...] [-- CLIENT-ARGS...]
+
+Starts fast-server, then for every case of the matrix starts a proxy server
+and a proxy client, runs the case's workload with bench-client through the
+client's SOCKS5 port and prints a summary. A no-proxy baseline of every
+workload runs first. Cases are named implementation/protocol/params@workload,
+e.g. sing-box/tuic/ed25519/bbr@download; with FILTERs only the cases whose
+name contains one of them run.
+
+options:
+  -m, --matrix PATH  TOML file with the matrix, default bench.toml
+  -t, --tag TAG      only run cases tagged TAG; a case is tagged with its
+                     implementation, protocol, parameters and workload as
+                     well as the matrix's tags; repeat to require several
+  --list             print the cases with their matrix tags and exit
+  --json PATH        also write one JSON line per case to PATH
+  --max-cv PCT       mark cases whose repeated runs have a coefficient of
+                     variation above PCT percent as unstable, default 5
+  --ready-timeout T  how long a process may take to come up, default 10s;
+                     fast-server and proxy servers have to accept
+                     connections, proxy clients a SOCKS5 CONNECT to
+                     fast-server
+  -- ARGS            pass ARGS on to every bench-client run, e.g.
+                     -- --duration 10s -c 4
+";
+
+const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest coefficient of variation of repeated runs, as a fraction, as
+/// bench-client's `--max-cv`.
+const DEFAULT_MAX_CV: f64 = 0.05;
+
+struct Args {
+    matrix: PathBuf,
+    filters: Vec<String>,
+    tags: Vec<String>,
+    list: bool,
+    json: Option<PathBuf>,
+    ready_timeout: Duration,
+    max_cv: f64,
+    client_args: Vec<String>,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut parsed = Args {
+            matrix: "bench.toml".into(),
+            filters: Vec::new(),
+            tags: Vec::new(),
+            list: false,
+            json: None,
+            ready_timeout: DEFAULT_READY_TIMEOUT,
+            max_cv: DEFAULT_MAX_CV,
+            client_args: Vec::new(),
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+            match arg.as_str() {
+                "-h" | "--help" => return Err(USAGE.into()),
+                "-m" | "--matrix" => parsed.matrix = value()?.into(),
+                "-t" | "--tag" => parsed.tags.push(value()?),
+                "--list" => parsed.list = true,
+                "--json" => parsed.json = Some(value()?.into()),
+                "--ready-timeout" => {
+                    let t = value()?;
+                    parsed.ready_timeout = bench_payload::parse_duration(&t)
+                        .filter(|t| !t.is_zero())
+                        .ok_or(format!("bad --ready-timeout {t:?}"))?;
+                }
+                "--max-cv" => {
+                    let pct = value()?;
+                    parsed.max_cv = pct
+                        .trim_end_matches('%')
+                        .parse()
+                        .ok()
+                        .filter(|&p: &f64| p >= 0.0)
+                        .map(|p| p / 100.0)
+                        .ok_or(format!("bad --max-cv {pct:?}"))?;
+                }
+                "--" => parsed.client_args.extend(args.by_ref()),
+                s if s.starts_with('-') => return Err(format!("unknown option {s:?}\n\n{USAGE}")),
+                s => parsed.filters.push(s.into()),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Outcome of one case, or of a baseline when `case` is `None`.
+struct Outcome<'a> {
+    name: String,
+    case: Option<&'a Case>,
+    workload: &'a Workload,
+    /// One report per repetition.
+    result: anyhow::Result<Vec<Report>>,
+    /// How the case's proxies ended.
+    exits: Vec<Exit>,
+    /// Coefficient of variation above which the runs count as unstable.
+    max_cv: f64,
+    /// Cut short by Ctrl-C; `result` and `exits` are what it got to.
+    interrupted: bool,
+}
+
+impl Outcome<'_> {
+    fn is_ok(&self) -> bool {
+        !self.interrupted
+            && self
+                .result
+                .as_ref()
+                .is_ok_and(|reports| reports.iter().all(|r| r.intact))
+    }
+
+    /// Summary of the runs' headline numbers, and their unit.
+    fn summary(&self) -> Option<(Summary, &'static str)> {
+        let headlines: Vec<_> = self
+            .result
+            .as_ref()
+            .ok()?
+            .iter()
+            .map(Report::headline)
+            .collect::<Option<_>>()?;
+        let (_, unit) = *headlines.first()?;
+        let values: Vec<_> = headlines.iter().map(|&(value, _)| value).collect();
+        Some((Summary::of(&values)?, unit))
+    }
+
+    /// Whether the runs agree closely enough for the mean to be trusted.
+    fn is_stable(&self) -> bool {
+        self.summary()
+            .is_none_or(|(summary, _)| summary.cv() <= self.max_cv)
+    }
+
+    fn to_json(&self) -> Value {
+        let (implementation, protocol, params, tags) = match self.case {
+            Some(case) => (
+                Some(case.implementation.name()),
+                Some(case.protocol.kind()),
+                Some(case.protocol.to_string()),
+                case.tags.iter().map(|t| t.as_str().into()).collect(),
+            ),
+            None => (None, None, None, Vec::new()),
+        };
+        let (error, runs) = match &self.result {
+            _ if self.interrupted => (
+                Some("interrupted".to_owned()),
+                self.result
+                    .iter()
+                    .flatten()
+                    .map(|r| r.json.clone())
+                    .collect(),
+            ),
+            Ok(reports) => (
+                (!self.is_ok()).then(|| "body not intact".to_owned()),
+                reports.iter().map(|r| r.json.clone()).collect(),
+            ),
+            Err(e) => (Some(format!("{e:#}")), Vec::new()),
+        };
+        let summary = self.summary();
+        object([
+            ("case", self.name.as_str().into()),
+            ("implementation", implementation.into()),
+            ("protocol", protocol.into()),
+            ("params", params.into()),
+            ("workload", self.workload.name.as_str().into()),
+            ("tags", Value::Array(tags)),
+            ("ok", self.is_ok().into()),
+            ("error", error.into()),
+            ("value", summary.as_ref().map(|(s, _)| s.mean).into()),
+            ("unit", summary.as_ref().map(|&(_, unit)| unit).into()),
+            (
+                "summary",
+                summary.as_ref().map_or(Value::Null, |(s, _)| s.to_json()),
+            ),
+            ("max_cv", self.max_cv.into()),
+            ("stable", self.is_stable().into()),
+            ("interrupted", self.interrupted.into()),
+            ("runs", Value::Array(runs)),
+            (
+                "processes",
+                Value::Array(self.exits.iter().map(Exit::to_json).collect()),
+            ),
+        ])
+    }
+}
+
+fn main() -> ExitCode {
+    if let Err(e) = supervisor::install() {
+        eprintln!("proxy-bench: signal handling: {e}");
+        return ExitCode::FAILURE;
+    }
+    _ = dotenvy::dotenv();
+
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("proxy-bench: {e:#}");
+            if supervisor::interrupted() {
+                ExitCode::from(130)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
+
+/// Runs every selected case; true if all of them succeeded.
+fn run(args: &Args) -> anyhow::Result<bool> {
+    let cases: Vec<_> = matrix::load(&args.matrix)?
+        .into_iter()
+        .filter(|case| {
+            let name = case.name();
+            (args.filters.is_empty() || args.filters.iter().any(|f| name.contains(f.as_str())))
+                && args.tags.iter().all(|tag| case.has_tag(tag))
+        })
+        .collect();
+    if args.list {
+        for case in &cases {
+            println!("{}  [{}]", case.name(), case.tags.join(", "));
+        }
+        return Ok(true);
+    }
+    if cases.is_empty() {
+        anyhow::bail!("no case matches");
+    }
+
+    let dir = WorkDir::create()?;
+    let fast_server_exits = Exits::default();
+    let (port, mut fast_server) = ports::retry([false], |[port]| {
+        let mut cmd = Command::new(tool("fast-server"));
+        cmd.env("HTTP_SERVER_PORT", port.to_string());
+        let mut fast_server = Process::spawn(
+            "fast-server",
+            cmd,
+            dir.path().join("fast-server.log"),
+            &fast_server_exits,
+        )?;
+        ready::listening(
+            &mut fast_server,
+            port,
+            false,
+            Instant::now() + args.ready_timeout,
+        )?;
+        Ok((port, fast_server))
+    })?;
+
+    let bench_client = tool("bench-client");
+    let run_workload = |workload: &Workload, repeat: usize, proxy: Option<u16>| {
+        let client_args: Vec<_> = workload
+            .args
+            .iter()
+            .chain(&args.client_args)
+            .cloned()
+            .collect();
+        (1..=repeat)
+            .map(|i| {
+                workload::run(&bench_client, &client_args, port, proxy)
+                    .with_context(|| format!("run {i}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+    };
+
+    let mut outcomes = Vec::new();
+    let mut baselines: Vec<&Workload> = Vec::new();
+    for case in &cases {
+        if !baselines.iter().any(|w| w.name == case.workload.name) {
+            baselines.push(&case.workload);
+        }
+    }
+    for workload in baselines {
+        // As many runs as the case that repeats it most.
+        let repeat = cases
+            .iter()
+            .filter(|c| c.workload.name == workload.name)
+            .map(|c| c.repeat)
+            .max()
+            .unwrap_or(1);
+        let name = format!("no-proxy@{}", workload.name);
+        println!("\n=== {name} ===");
+        let result = run_workload(workload, repeat, None);
+        let baseline = Outcome {
+            name,
+            case: None,
+            workload,
+            result,
+            exits: Vec::new(),
+            max_cv: args.max_cv,
+            interrupted: supervisor::interrupted(),
+        };
+        print_outcome(&baseline);
+        if baseline.interrupted {
+            outcomes.push(baseline);
+            break;
+        }
+        // Nothing behind a proxy can work if the direct run does not.
+        if let Err(e) = &baseline.result {
+            anyhow::bail!("{} failed: {e:#}", baseline.name);
+        }
+        outcomes.push(baseline);
+    }
+
+    let mut certs = HashMap::new();
+    for case in &cases {
+        if supervisor::interrupted() {
+            break;
+        }
+        let name = case.name();
+        println!("\n=== {name} ===");
+        let exits = Exits::default();
+        let result = run_case(
+            case,
+            dir.path(),
+            (port, args.ready_timeout),
+            &mut certs,
+            &exits,
+            |client_port| run_workload(&case.workload, case.repeat, Some(client_port)),
+        );
+        let outcome = Outcome {
+            name,
+            case: Some(case),
+            workload: &case.workload,
+            result,
+            exits: exits.take(),
+            max_cv: args.max_cv,
+            interrupted: supervisor::interrupted(),
+        };
+        print_outcome(&outcome);
+        let interrupted = outcome.interrupted;
+        outcomes.push(outcome);
+        if interrupted {
+            break;
+        }
+        fast_server.check()?;
+    }
+
+    drop(fast_server);
+    for exit in fast_server_exits.take() {
+        if exit.killed {
+            eprintln!("proxy-bench: fast-server ignored SIGTERM and was killed");
+        }
+    }
+
+    print_summary(&outcomes);
+    if let Some(path) = &args.json {
+        let mut file =
+            fs::File::create(path).with_context(|| format!("create {}", path.display()))?;
+        for outcome in &outcomes {
+            writeln!(file, "{}", outcome.to_json())?;
+        }
+    }
+    if supervisor::interrupted() {
+        let done = outcomes
+            .iter()
+            .filter(|o| o.case.is_some() && !o.interrupted)
+            .count();
+        anyhow::bail!("interrupted, {done} of {} cases done", cases.len());
+    }
+    Ok(outcomes.iter().all(Outcome::is_ok))
+}
+
+/// Starts the case's server and client on free ports, waits until the client
+/// reaches fast-server on `target`, runs `workload` against the client's port
+/// and stops both again.
+fn run_case(
+    case: &Case,
+    dir: &Path,
+    (target, ready_timeout): (u16, Duration),
+    certs: &mut HashMap<KeyType, Cert>,
+    exits: &Exits,
+    workload: impl FnOnce(u16) -> anyhow::Result<Vec<Report>>,
+) -> anyhow::Result<Vec<Report>> {
+    let cert = match case.protocol.key() {
+        Some(key) => Some(match certs.entry(key) {
+            Entry::Occupied(cert) => &*cert.into_mut(),
+            Entry::Vacant(slot) => &*slot.insert(certs::generate(dir, key)?),
+        }),
+        None => None,
+    };
+    let secrets = Secrets::generate(&case.protocol).context("generate credentials")?;
+    let stem = case.name().replace(['/', '@'], "-");
+    let (client_port, mut server, mut client) = ports::retry(
+        [case.protocol.udp(), false],
+        |[server_port, client_port]| {
+            let setup = Setup {
+                dir,
+                stem: stem.clone(),
+                server_port,
+                client_port,
+                secrets: &secrets,
+                cert,
+            };
+            let (server, client) = case.implementation.commands(&case.protocol, &setup)?;
+
+            let deadline = Instant::now() + ready_timeout;
+            let mut server = Process::spawn(
+                "server",
+                server,
+                dir.join(format!("{stem}-server.log")),
+                exits,
+            )?;
+            ready::listening(&mut server, server_port, case.protocol.udp(), deadline)?;
+            let mut client = Process::spawn(
+                "client",
+                client,
+                dir.join(format!("{stem}-client.log")),
+                exits,
+            )?;
+            ready::listening(&mut client, client_port, false, deadline)?;
+            ready::socks(&mut client, client_port, target, deadline)?;
+            Ok((client_port, server, client))
+        },
+    )?;
+
+    let reports = workload(client_port);
+    // A proxy that died mid-run explains a failed workload better than
+    // bench-client's error does.
+    server.check()?;
+    client.check()?;
+    reports.map_err(|e| anyhow::anyhow!("{e:#}{}{}", server.stderr(), client.stderr()))
+}
+
+fn print_outcome(outcome: &Outcome) {
+    match &outcome.result {
+        _ if outcome.interrupted => println!("  interrupted"),
+        Ok(reports) => {
+            if reports.len() > 1 {
+                for (i, report) in reports.iter().enumerate() {
+                    println!("  run {}: {}", i + 1, format_headline(report.headline()));
+                }
+                let summary = outcome.summary();
+                println!(
+                    "  mean:  {}{}",
+                    format_headline(summary.as_ref().map(|(s, unit)| (s.mean, *unit))),
+                    format_spread(outcome)
+                );
+                if let Some((summary, _)) = &summary {
+                    println!(
+                        "  median {:.1}, stddev {:.1}, min {:.1}, max {:.1}",
+                        summary.median, summary.stddev, summary.min, summary.max
+                    );
+                }
+            } else {
+                for report in reports {
+                    println!("  {}", format_headline(report.headline()));
+                }
+            }
+            if !outcome.is_ok() {
+                println!("  body not intact");
+            }
+        }
+        Err(e) => println!("  FAILED: {e:#}"),
+    }
+    for exit in &outcome.exits {
+        if exit.killed {
+            println!("  {} ignored SIGTERM and was killed", exit.name);
+        }
+    }
+}
+
+fn print_summary(outcomes: &[Outcome]) {
+    let width = outcomes.iter().map(|o| o.name.len()).max().unwrap_or(0);
+    let lines: Vec<_> = outcomes
+        .iter()
+        .map(|outcome| match &outcome.result {
+            _ if outcome.interrupted => format!("{:width$}  interrupted", outcome.name),
+            Ok(_) => format!(
+                "{:width$}  {}{}{}",
+                outcome.name,
+                format_headline(outcome.summary().map(|(s, unit)| (s.mean, unit))),
+                format_spread(outcome),
+                if outcome.is_ok() { "" } else { " (not intact)" }
+            ),
+            Err(_) => format!("{:width$}  FAILED", outcome.name),
+        })
+        .collect();
+    let rule = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    println!("\n{}", "=".repeat(rule));
+    for line in &lines {
+        println!("{line}");
+    }
+    println!("{}", "-".repeat(rule));
+}
+
+/// The 95% confidence interval and coefficient of variation of repeated
+/// runs, flagged if they vary more than `--max-cv` allows; nothing for a
+/// single run.
+fn format_spread(outcome: &Outcome) -> String {
+    let Some((summary, _)) = outcome.summary() else {
+        return String::new();
+    };
+    let Some((low, high)) = summary.ci95 else {
+        return String::new();
+    };
+    format!(
+        "  95% CI {low:.1}..{high:.1}, cv {:.1}%{}",
+        summary.cv() * 100.0,
+        if outcome.is_stable() {
+            ""
+        } else {
+            " (unstable)"
+        }
+    )
+}
+
+fn format_headline(headline: Option<(f64, &str)>) -> String {
+    match headline {
+        Some((mib, "MiB/s")) => {
+            // MiB/s to decimal gigabits.
+            let gbps = mib * 1.048576 * 8.0 / 1000.0;
+            format!("{mib:8.1} MiB/s  ≈ {gbps:6.2} Gbps")
+        }
+        Some((value, unit)) => format!("{value:8.1} {unit}"),
+        None => "no result".into(),
+    }
+}
+
+/// `name` next to this executable, as cargo builds the workspace, or else
+/// whatever `PATH` has.
+fn tool(name: &str) -> PathBuf {
+    env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join(name)))
+        .filter(|path| path.is_file())
+        .unwrap_or_else(|| name.into())
+}
+
+/// Scratch directory for configs, certificates and logs, removed on drop.
+struct WorkDir(PathBuf);
+
+impl WorkDir {
+    fn create() -> anyhow::Result<Self> {
+        let path = env::temp_dir().join(format!("proxy-bench-{}", std::process::id()));
+        fs::create_dir_all(&path).with_context(|| format!("create {}", path.display()))?;
+        Ok(Self(path))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,451 @@
+//! The benchmark matrix, read from a TOML file. Every `[[matrix]]` entry lists
+//! implementations, protocols, their parameters and workloads, and stands for
+//! every combination of them:
+//!
+//! ```toml
+//! repeat = 1
+//!
+//! [workloads]
+//! rps = { args = ["--rps", "-c", "16"] }
+//!
+//! [[matrix]]
+//! tags = ["tls"]
+//! implementations = ["sing-box"]
+//! protocols = ["trojan", "tuic"]
+//! keys = ["rsa4096", "ed25519"]
+//! congestion = ["bbr", "new_reno"]   # tuic only
+//! workloads = ["download", "rps"]
+//! repeat = 3
+//! ```
+//!
+//! `ciphers` parameterizes shadowsocks, `keys` the TLS-based protocols.
+//! Combinations an implementation does not support are left out.
+
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::Context as _;
+use toml_edit::{DocumentMut, Item, TableLike};
+
+use crate::protocol::{Case, KeyType, Protocol};
+use crate::proxies;
+use crate::workload::Workload;
+
+/// Workload every matrix has, a plain download of `/bench`.
+const DOWNLOAD: &str = "download";
+
+pub fn load(path: &Path) -> anyhow::Result<Vec<Case>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    parse(&text).with_context(|| format!("{}", path.display()))
+}
+
+fn parse(text: &str) -> anyhow::Result<Vec<Case>> {
+    let doc: DocumentMut = text.parse()?;
+    let top = doc.as_table();
+    check_keys(top, &["repeat", "workloads", "matrix"])?;
+    let repeat = positive(top.get("repeat"), "repeat")?.unwrap_or(1);
+
+    let mut workloads = vec![Rc::new(Workload {
+        name: DOWNLOAD.into(),
+        args: Vec::new(),
+    })];
+    if let Some(item) = top.get("workloads") {
+        let table = item.as_table_like().context("workloads must be a table")?;
+        for (name, item) in table.iter() {
+            let workload = item
+                .as_table_like()
+                .with_context(|| format!("workloads.{name} must be a table"))?;
+            check_keys(workload, &["args"]).with_context(|| format!("workloads.{name}"))?;
+            let args = strings(workload.get("args"), "args")?.unwrap_or_default();
+            let workload = Rc::new(Workload {
+                name: name.into(),
+                args,
+            });
+            match workloads.iter_mut().find(|w| w.name == name) {
+                Some(w) => *w = workload,
+                None => workloads.push(workload),
+            }
+        }
+    }
+
+    let entries = top
+        .get("matrix")
+        .and_then(Item::as_array_of_tables)
+        .context("no [[matrix]] entries")?;
+    let mut cases: Vec<Case> = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let expanded =
+            expand(entry, &workloads, repeat).with_context(|| format!("[[matrix]] #{}", i + 1))?;
+        for case in expanded {
+            // The same case from two entries runs once, with both sets of tags.
+            match cases.iter_mut().find(|c| c.name() == case.name()) {
+                Some(c) => {
+                    for tag in case.tags {
+                        if !c.tags.contains(&tag) {
+                            c.tags.push(tag);
+                        }
+                    }
+                }
+                None => cases.push(case),
+            }
+        }
+    }
+    Ok(cases)
+}
+
+/// Every combination one `[[matrix]]` entry stands for.
+fn expand(
+    entry: &dyn TableLike,
+    workloads: &[Rc<Workload>],
+    repeat: usize,
+) -> anyhow::Result<Vec<Case>> {
+    check_keys(
+        entry,
+        &[
+            "tags",
+            "implementations",
+            "protocols",
+            "ciphers",
+            "keys",
+            "congestion",
+            "workloads",
+            "repeat",
+        ],
+    )?;
+    let list = |name| strings(entry.get(name), name);
+    let tags = list("tags")?.unwrap_or_default();
+    let repeat = positive(entry.get("repeat"), "repeat")?.unwrap_or(repeat);
+    let implementations = list("implementations")?
+        .context("implementations missing")?
+        .iter()
+        .map(|name| proxies::find(name).with_context(|| format!("unknown implementation {name:?}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let keys = list("keys")?
+        .unwrap_or_default()
+        .iter()
+        .map(|name| KeyType::parse(name).with_context(|| format!("unknown key type {name:?}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let ciphers = list("ciphers")?.unwrap_or_default();
+    let congestion = list("congestion")?.unwrap_or_else(|| vec!["bbr".into()]);
+    let workloads = list("workloads")?
+        .unwrap_or_else(|| vec![DOWNLOAD.into()])
+        .iter()
+        .map(|name| {
+            workloads
+                .iter()
+                .find(|w| w.name == *name)
+                .cloned()
+                .with_context(|| format!("unknown workload {name:?}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut protocols = Vec::new();
+    for kind in list("protocols")?.context("protocols missing")? {
+        let before = protocols.len();
+        match kind.as_str() {
+            "shadowsocks" => protocols.extend(ciphers.iter().map(|cipher| Protocol::Shadowsocks {
+                cipher: cipher.clone(),
+            })),
+            "trojan" => protocols.extend(keys.iter().map(|&key| Protocol::Trojan { key })),
+            "anytls" => protocols.extend(keys.iter().map(|&key| Protocol::AnyTls { key })),
+            "tuic" => {
+                for congestion in &congestion {
+                    protocols.extend(keys.iter().map(|&key| Protocol::Tuic {
+                        key,
+                        congestion: congestion.clone(),
+                    }));
+                }
+            }
+            kind => anyhow::bail!("unknown protocol {kind:?}"),
+        }
+        if protocols.len() == before {
+            let needs = if kind == "shadowsocks" {
+                "ciphers"
+            } else {
+                "keys"
+            };
+            anyhow::bail!("{kind} needs {needs}");
+        }
+    }
+
+    let mut cases = Vec::new();
+    for &implementation in &implementations {
+        for protocol in &protocols {
+            if !implementation.protocols().contains(&protocol.kind()) {
+                continue;
+            }
+            for workload in &workloads {
+                cases.push(Case {
+                    implementation,
+                    protocol: protocol.clone(),
+                    workload: workload.clone(),
+                    repeat,
+                    tags: tags.clone(),
+                });
+            }
+        }
+    }
+    if cases.is_empty() {
+        anyhow::bail!("none of the implementations supports any of the protocols");
+    }
+    Ok(cases)
+}
+
+/// Rejects keys not in `known`, which are most likely typos.
+fn check_keys(table: &dyn TableLike, known: &[&str]) -> anyhow::Result<()> {
+    for (key, _) in table.iter() {
+        if !known.contains(&key) {
+            anyhow::bail!("unknown key {key:?}, expected one of {}", known.join(", "));
+        }
+    }
+    Ok(())
+}
+
+/// A string or an array of strings.
+fn strings(item: Option<&Item>, name: &str) -> anyhow::Result<Option<Vec<String>>> {
+    let Some(item) = item else {
+        return Ok(None);
+    };
+    if let Some(s) = item.as_str() {
+        return Ok(Some(vec![s.into()]));
+    }
+    let array = item
+        .as_array()
+        .with_context(|| format!("{name} must be a string or an array of strings"))?;
+    array
+        .iter()
+        .map(|v| {
+            v.as_str()
+                .map(Into::into)
+                .with_context(|| format!("{name} must be a string or an array of strings"))
+        })
+        .collect::<anyhow::Result<_>>()
+        .map(Some)
+}
+
+fn positive(item: Option<&Item>, name: &str) -> anyhow::Result<Option<usize>> {
+    item.map(|item| {
+        item.as_integer()
+            .and_then(|n| usize::try_from(n).ok())
+            .filter(|&n| n > 0)
+            .with_context(|| format!("{name} must be a positive integer"))
+    })
+    .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(text: &str) -> Vec<String> {
+        parse(text).unwrap().iter().map(Case::name).collect()
+    }
+
+    fn error(text: &str) -> String {
+        match parse(text) {
+            Ok(_) => panic!("parsed: {text}"),
+            Err(e) => format!("{e:#}"),
+        }
+    }
+
+    #[test]
+    fn bundled_matrix() {
+        let cases = parse(include_str!("../../bench.toml")).unwrap();
+        assert!(!cases.is_empty());
+        assert!(cases.iter().all(|c| c.repeat >= 1));
+    }
+
+    #[test]
+    fn expands_every_supported_combination() {
+        let text = r#"
+            [[matrix]]
+            implementations = ["sing-box", "anytls-rs"]
+            protocols = ["tuic", "anytls"]
+            keys = ["rsa4096", "ed25519"]
+            congestion = ["bbr", "cubic"]
+        "#;
+        assert_eq!(
+            names(text),
+            [
+                "sing-box/tuic/rsa4096/bbr@download",
+                "sing-box/tuic/ed25519/bbr@download",
+                "sing-box/tuic/rsa4096/cubic@download",
+                "sing-box/tuic/ed25519/cubic@download",
+                "sing-box/anytls/rsa4096@download",
+                "sing-box/anytls/ed25519@download",
+                "anytls-rs/anytls/rsa4096@download",
+                "anytls-rs/anytls/ed25519@download",
+            ]
+        );
+    }
+
+    #[test]
+    fn workloads_and_repeat() {
+        let text = r#"
+            repeat = 2
+
+            [workloads]
+            download = { args = ["--size", "1GiB"] }
+            rps = { args = ["--rps"] }
+
+            [[matrix]]
+            implementations = "mihomo"
+            protocols = "shadowsocks"
+            ciphers = "none"
+            workloads = ["download", "rps"]
+
+            [[matrix]]
+            implementations = "shadowsocks-rust"
+            protocols = "shadowsocks"
+            ciphers = "none"
+            repeat = 5
+        "#;
+        let cases = parse(text).unwrap();
+        let summary: Vec<_> = cases
+            .iter()
+            .map(|c| (c.name(), c.workload.args.clone(), c.repeat))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "mihomo/shadowsocks/none@download".into(),
+                    vec!["--size".to_owned(), "1GiB".into()],
+                    2
+                ),
+                (
+                    "mihomo/shadowsocks/none@rps".into(),
+                    vec!["--rps".into()],
+                    2
+                ),
+                (
+                    "shadowsocks-rust/shadowsocks/none@download".into(),
+                    vec!["--size".into(), "1GiB".into()],
+                    5
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn tags() {
+        let text = r#"
+            [[matrix]]
+            tags = ["tls"]
+            implementations = ["sing-box"]
+            protocols = ["tuic"]
+            keys = ["ed25519"]
+
+            [[matrix]]
+            tags = ["quic", "tls"]
+            implementations = ["sing-box"]
+            protocols = ["tuic"]
+            keys = ["ed25519"]
+        "#;
+        // The same case from two entries runs once, with both sets of tags.
+        let cases = parse(text).unwrap();
+        assert_eq!(cases.len(), 1);
+        let case = &cases[0];
+        assert_eq!(case.tags, ["tls", "quic"]);
+        for tag in [
+            "tls", "quic", "sing-box", "tuic", "ed25519", "bbr", "download",
+        ] {
+            assert!(case.has_tag(tag), "{tag}");
+        }
+        for tag in ["rsa4096", "mihomo", "shadowsocks", "ed", "tuic/ed25519"] {
+            assert!(!case.has_tag(tag), "{tag}");
+        }
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert!(error("repeats = 1\n[[matrix]]").contains(r#"unknown key "repeats""#));
+        let text = r#"
+            [[matrix]]
+            implementations = "mihomo"
+            protocols = "shadowsocks"
+            cipher = "none"
+        "#;
+        let e = error(text);
+        assert!(
+            e.contains("[[matrix]] #1") && e.contains(r#"unknown key "cipher""#),
+            "{e}"
+        );
+        let text = r#"
+            [workloads]
+            rps = { arg = ["--rps"] }
+            [[matrix]]
+        "#;
+        let e = error(text);
+        assert!(
+            e.contains("workloads.rps") && e.contains(r#"unknown key "arg""#),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn empty_matrix() {
+        assert!(error("").contains("no [[matrix]] entries"));
+        assert!(error("repeat = 1").contains("no [[matrix]] entries"));
+        let text = r#"
+            [[matrix]]
+            implementations = ["mihomo", "anytls-rs"]
+            protocols = ["trojan"]
+            keys = ["ed25519"]
+        "#;
+        assert!(error(text).contains("none of the implementations supports"));
+    }
+
+    #[test]
+    fn invalid_entries() {
+        let entry = |fields: &str| format!("[[matrix]]\n{fields}");
+        for (fields, message) in [
+            (r#"protocols = "trojan""#, "implementations missing"),
+            (r#"implementations = "sing-box""#, "protocols missing"),
+            (
+                r#"implementations = "v2ray"
+                protocols = "trojan""#,
+                r#"unknown implementation "v2ray""#,
+            ),
+            (
+                r#"implementations = "sing-box"
+                protocols = "vmess""#,
+                r#"unknown protocol "vmess""#,
+            ),
+            (
+                r#"implementations = "sing-box"
+                protocols = "trojan"
+                keys = "dsa""#,
+                r#"unknown key type "dsa""#,
+            ),
+            (
+                r#"implementations = "sing-box"
+                protocols = "shadowsocks""#,
+                "shadowsocks needs ciphers",
+            ),
+            (
+                r#"implementations = "sing-box"
+                protocols = "trojan""#,
+                "trojan needs keys",
+            ),
+            (
+                r#"implementations = "sing-box"
+                protocols = "shadowsocks"
+                ciphers = "none"
+                workloads = "upload""#,
+                r#"unknown workload "upload""#,
+            ),
+            (
+                r#"implementations = ["sing-box", 1]"#,
+                "implementations must be a string or an array of strings",
+            ),
+            (r#"repeat = 0"#, "repeat must be a positive integer"),
+            (r#"repeat = "2""#, "repeat must be a positive integer"),
+        ] {
+            let e = error(&entry(fields));
+            assert!(e.contains(message), "{fields}: {e}");
+        }
+        assert!(error("matrix = 1").contains("no [[matrix]] entries"));
+        assert!(error("workloads = 1\n[[matrix]]").contains("workloads must be a table"));
+    }
+}
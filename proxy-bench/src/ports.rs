@@ -0,0 +1,105 @@
+//! Ports for fast-server and the proxies, picked by the kernel so that runs
+//! next to each other or right after each other do not collide.
+
+use std::io;
+use std::net::{Ipv4Addr, TcpListener, UdpSocket};
+
+/// How often a process is restarted on fresh ports after finding its port
+/// taken.
+const ATTEMPTS: usize = 3;
+
+/// Ports nothing is bound to right now, one for each entry of `udp`: a UDP
+/// port if it is true, else a TCP one. All of them are held until the last is
+/// picked, so they differ.
+pub fn free<const N: usize>(udp: [bool; N]) -> io::Result<[u16; N]> {
+    let mut tcp_held = Vec::new();
+    let mut udp_held = Vec::new();
+    let mut ports = [0; N];
+    for (port, udp) in ports.iter_mut().zip(udp) {
+        if udp {
+            let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+            *port = socket.local_addr()?.port();
+            udp_held.push(socket);
+        } else {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+            *port = listener.local_addr()?.port();
+            tcp_held.push(listener);
+        }
+    }
+    Ok(ports)
+}
+
+/// Calls `start` with [`free`] ports, and again with new ones if it fails
+/// because something else bound one of them between picking and starting.
+///
+/// That is judged by the ports, not by what the processes printed: `start`
+/// stops its processes when it fails, so a port that is still taken
+/// afterwards belongs to someone else.
+pub fn retry<T, const N: usize>(
+    udp: [bool; N],
+    mut start: impl FnMut([u16; N]) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let mut attempt = 1;
+    loop {
+        let ports = free(udp)?;
+        let result = start(ports);
+        let stolen = result.is_err() && ports.iter().zip(udp).any(|(&p, udp)| taken(p, udp));
+        if !stolen || attempt == ATTEMPTS {
+            return result;
+        }
+        attempt += 1;
+    }
+}
+
+/// Whether binding `port` fails with `EADDRINUSE`.
+fn taken(port: u16, udp: bool) -> bool {
+    let result = if udp {
+        UdpSocket::bind((Ipv4Addr::LOCALHOST, port)).map(drop)
+    } else {
+        TcpListener::bind((Ipv4Addr::LOCALHOST, port)).map(drop)
+    };
+    matches!(result, Err(e) if e.kind() == io::ErrorKind::AddrInUse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_when_another_program_holds_a_port() {
+        let mut thief = Vec::new();
+        let mut attempts = 0;
+        let port = retry([false, true], |[tcp, udp]| {
+            attempts += 1;
+            if attempts == 1 {
+                thief.push(TcpListener::bind((Ipv4Addr::LOCALHOST, tcp))?);
+                anyhow::bail!("exited with exit status: 1");
+            }
+            Ok((tcp, udp))
+        })
+        .unwrap();
+        assert_eq!(attempts, 2);
+        assert_ne!(port.0, thief[0].local_addr().unwrap().port());
+
+        let mut thief = Vec::new();
+        let mut attempts = 0;
+        let result: anyhow::Result<()> = retry([true], |[udp]| {
+            attempts += 1;
+            thief.push(UdpSocket::bind((Ipv4Addr::LOCALHOST, udp))?);
+            anyhow::bail!("exited with exit status: 1")
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, ATTEMPTS);
+    }
+
+    #[test]
+    fn other_failures_are_not_retried() {
+        let mut attempts = 0;
+        let result: anyhow::Result<()> = retry([false], |_| {
+            attempts += 1;
+            anyhow::bail!("Address already in use (os error 98)")
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,165 @@
+//! Child processes of a run. Their stderr goes to a log file so a process
+//! that dies can be reported with its last words.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::os::unix::process::ExitStatusExt as _;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Context as _;
+
+use crate::supervisor;
+use bench_payload::json::{Value, object};
+
+/// How much of a log an error quotes.
+const TAIL_LINES: usize = 20;
+
+/// How long a process has to exit after SIGTERM before it gets SIGKILL.
+const GRACE: Duration = Duration::from_secs(5);
+
+/// How a child ended.
+pub struct Exit {
+    pub name: String,
+    /// `None` if it could not be waited for.
+    pub status: Option<ExitStatus>,
+    /// Whether it outlived [`GRACE`] and had to be killed.
+    pub killed: bool,
+    /// The last lines of its stderr.
+    pub stderr: Vec<String>,
+}
+
+impl Exit {
+    pub fn to_json(&self) -> Value {
+        object([
+            ("name", self.name.as_str().into()),
+            ("code", self.status.and_then(|s| s.code()).into()),
+            ("signal", self.status.and_then(|s| s.signal()).into()),
+            ("killed", self.killed.into()),
+            (
+                "stderr",
+                Value::Array(self.stderr.iter().map(|l| l.as_str().into()).collect()),
+            ),
+        ])
+    }
+}
+
+/// Where processes leave their [`Exit`] once stopped.
+#[derive(Clone, Default)]
+pub struct Exits(Rc<RefCell<Vec<Exit>>>);
+
+impl Exits {
+    pub fn take(&self) -> Vec<Exit> {
+        self.0.take()
+    }
+}
+
+/// A running child in a process group of its own, stopped when dropped.
+pub struct Process {
+    name: String,
+    child: Child,
+    log: PathBuf,
+    exits: Exits,
+}
+
+impl Process {
+    pub fn spawn(
+        name: impl Into<String>,
+        mut cmd: Command,
+        log: PathBuf,
+        exits: &Exits,
+    ) -> anyhow::Result<Self> {
+        let name = name.into();
+        let stderr = File::create(&log).with_context(|| format!("create {}", log.display()))?;
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(stderr);
+        supervisor::isolate(&mut cmd);
+        let child = cmd
+            .spawn()
+            .with_context(|| format!("{name}: start {}", cmd.get_program().to_string_lossy()))?;
+        supervisor::register(child.id());
+        Ok(Self {
+            name,
+            child,
+            log,
+            exits: exits.clone(),
+        })
+    }
+
+    /// Fails with the end of its stderr if the process has exited.
+    pub fn check(&mut self) -> anyhow::Result<()> {
+        match self.child.try_wait()? {
+            None => Ok(()),
+            Some(status) => Err(self.error(format!("exited with {status}"))),
+        }
+    }
+
+    /// `what` went wrong with the process, followed by the end of its stderr.
+    pub fn error(&self, what: impl std::fmt::Display) -> anyhow::Error {
+        anyhow::anyhow!("{} {what}{}", self.name, self.tail())
+    }
+
+    /// The end of its stderr under a heading, or nothing if it is empty.
+    pub fn stderr(&self) -> String {
+        match self.tail() {
+            tail if tail.is_empty() => tail,
+            tail => format!("\n  {} stderr:{tail}", self.name),
+        }
+    }
+
+    /// Sends SIGTERM to the process group, SIGKILL if the process is still
+    /// there after [`GRACE`], and records how it ended.
+    fn stop(&mut self) {
+        let pid = self.child.id();
+        supervisor::signal(pid, libc::SIGTERM);
+        let deadline = Instant::now() + GRACE;
+        let mut killed = false;
+        let status = loop {
+            match self.child.try_wait() {
+                Ok(Some(status)) => break Some(status),
+                Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(20)),
+                _ => {
+                    killed = true;
+                    supervisor::signal(pid, libc::SIGKILL);
+                    break self.child.wait().ok();
+                }
+            }
+        };
+        // Whatever the process left behind in its group.
+        supervisor::signal(pid, libc::SIGKILL);
+        supervisor::unregister(pid);
+        self.exits.0.borrow_mut().push(Exit {
+            name: self.name.clone(),
+            status,
+            killed,
+            stderr: self.tail_lines(),
+        });
+    }
+
+    /// The last lines of stderr, indented on lines of their own, or nothing.
+    fn tail(&self) -> String {
+        self.tail_lines()
+            .iter()
+            .map(|line| format!("\n    {line}"))
+            .collect()
+    }
+
+    fn tail_lines(&self) -> Vec<String> {
+        let log = std::fs::read_to_string(&self.log).unwrap_or_default();
+        let lines: Vec<_> = log.lines().collect();
+        lines[lines.len().saturating_sub(TAIL_LINES)..]
+            .iter()
+            .map(|line| line.to_string())
+            .collect()
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt;
+use std::io::{self, Read as _};
+use std::rc::Rc;
+
+use bench_payload::base64;
+
+use crate::proxies::Implementation;
+use crate::workload::Workload;
+
+/// Signature algorithm of the self-signed certificate TLS-based protocols use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyType {
+    Rsa4096,
+    Ed25519,
+}
+
+impl KeyType {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "rsa4096" | "rsa" => Some(KeyType::Rsa4096),
+            "ed25519" => Some(KeyType::Ed25519),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyType::Rsa4096 => "rsa4096",
+            KeyType::Ed25519 => "ed25519",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Protocol {
+    Shadowsocks { cipher: String },
+    Trojan { key: KeyType },
+    Tuic { key: KeyType, congestion: String },
+    AnyTls { key: KeyType },
+}
+
+impl Protocol {
+    /// `shadowsocks`, `trojan`, `tuic` or `anytls`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Protocol::Shadowsocks { .. } => "shadowsocks",
+            Protocol::Trojan { .. } => "trojan",
+            Protocol::Tuic { .. } => "tuic",
+            Protocol::AnyTls { .. } => "anytls",
+        }
+    }
+
+    /// Whether the server listens on UDP rather than TCP.
+    pub fn udp(&self) -> bool {
+        matches!(self, Protocol::Tuic { .. })
+    }
+
+    /// Certificate the server needs, if the protocol runs over TLS.
+    pub fn key(&self) -> Option<KeyType> {
+        match self {
+            Protocol::Shadowsocks { .. } => None,
+            Protocol::Trojan { key } | Protocol::Tuic { key, .. } | Protocol::AnyTls { key } => {
+                Some(*key)
+            }
+        }
+    }
+}
+
+/// What tells cases of one protocol apart, e.g. `aes-128-gcm` or
+/// `ed25519/bbr`.
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Shadowsocks { cipher } => f.write_str(cipher),
+            Protocol::Trojan { key } | Protocol::AnyTls { key } => f.write_str(key.name()),
+            Protocol::Tuic { key, congestion } => write!(f, "{}/{congestion}", key.name()),
+        }
+    }
+}
+
+/// One benchmark: a protocol run by one implementation on both ends, measured
+/// with one workload.
+pub struct Case {
+    pub implementation: &'static dyn Implementation,
+    pub protocol: Protocol,
+    pub workload: Rc<Workload>,
+    /// How many times the workload runs against the same proxies.
+    pub repeat: usize,
+    /// Tags from the matrix, on top of the ones [`Case::has_tag`] derives.
+    pub tags: Vec<String>,
+}
+
+impl Case {
+    /// `implementation/protocol/params@workload`, e.g.
+    /// `sing-box/tuic/ed25519/bbr@download`.
+    pub fn name(&self) -> String {
+        format!(
+            "{}/{}/{}@{}",
+            self.implementation.name(),
+            self.protocol.kind(),
+            self.protocol,
+            self.workload.name
+        )
+    }
+
+    /// Whether the matrix tagged the case with `tag`, or `tag` names its
+    /// implementation, protocol, one of its parameters or its workload.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+            || self.implementation.name() == tag
+            || self.protocol.kind() == tag
+            || self.protocol.to_string().split('/').any(|p| p == tag)
+            || self.workload.name == tag
+    }
+}
+
+/// Credentials shared by a case's server and client.
+pub struct Secrets {
+    pub password: String,
+    /// TUIC user id.
+    pub uuid: String,
+}
+
+impl Secrets {
+    /// Fresh credentials. Shadowsocks 2022 ciphers need a base64 key of
+    /// exactly the cipher's key length, the others take any password.
+    pub fn generate(protocol: &Protocol) -> io::Result<Self> {
+        let password = match protocol {
+            Protocol::Shadowsocks { cipher } if cipher.contains("128") => {
+                base64::encode(&random(16)?)
+            }
+            Protocol::Shadowsocks { .. } => base64::encode(&random(32)?),
+            protocol => format!("{}-{}", protocol.kind(), hex(&random(12)?)),
+        };
+        let mut id = random(16)?;
+        // Version 4, variant 1.
+        id[6] = (id[6] & 0x0f) | 0x40;
+        id[8] = (id[8] & 0x3f) | 0x80;
+        let id = hex(&id);
+        let uuid = format!(
+            "{}-{}-{}-{}-{}",
+            &id[..8],
+            &id[8..12],
+            &id[12..16],
+            &id[16..20],
+            &id[20..]
+        );
+        Ok(Self { password, uuid })
+    }
+}
+
+fn random(len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,39 @@
+use std::process::Command;
+
+use super::{Implementation, Setup};
+use crate::protocol::Protocol;
+
+/// `anytls-server` and `anytls-client` from anytls-rs.
+pub struct AnyTlsRs;
+
+impl Implementation for AnyTlsRs {
+    fn name(&self) -> &'static str {
+        "anytls-rs"
+    }
+
+    fn protocols(&self) -> &'static [&'static str] {
+        &["anytls"]
+    }
+
+    fn commands(&self, protocol: &Protocol, setup: &Setup) -> anyhow::Result<(Command, Command)> {
+        let Protocol::AnyTls { .. } = protocol else {
+            anyhow::bail!("{} speaks anytls only", self.name());
+        };
+        let cert = setup.cert()?;
+        let server_addr = format!("127.0.0.1:{}", setup.server_port);
+        let password = &setup.secrets.password;
+
+        let mut server = Command::new("anytls-server");
+        server.args(["-l", &server_addr, "-p", password]);
+        server
+            .arg("--cert")
+            .arg(&cert.cert)
+            .arg("--key")
+            .arg(&cert.key);
+        server.args(["-L", "error", "-M", "1"]);
+        let mut client = Command::new("anytls-client");
+        client.args(["-l", &format!("127.0.0.1:{}", setup.client_port)]);
+        client.args(["-s", &server_addr, "-p", password, "-L", "error", "-M", "1"]);
+        Ok((server, client))
+    }
+}
@@ -0,0 +1,83 @@
+use std::process::Command;
+
+use super::{Implementation, Setup};
+use crate::protocol::Protocol;
+use bench_payload::json::{Value, array, object};
+
+/// mihomo (Clash.Meta): a shadowsocks listener on the server, a `ss` proxy
+/// behind its SOCKS port on the client. Configs are JSON, which mihomo reads
+/// as YAML.
+pub struct Mihomo;
+
+impl Implementation for Mihomo {
+    fn name(&self) -> &'static str {
+        "mihomo"
+    }
+
+    fn protocols(&self) -> &'static [&'static str] {
+        &["shadowsocks"]
+    }
+
+    fn commands(&self, protocol: &Protocol, setup: &Setup) -> anyhow::Result<(Command, Command)> {
+        let Protocol::Shadowsocks { cipher } = protocol else {
+            anyhow::bail!("{} speaks shadowsocks only", self.name());
+        };
+        let cipher = Value::from(cipher.as_str());
+        let password = Value::from(setup.secrets.password.as_str());
+
+        let server = object([
+            ("log-level", "error".into()),
+            ("allow-lan", true.into()),
+            ("mode", "direct".into()),
+            (
+                "listeners",
+                array([object([
+                    ("name", "ss-in".into()),
+                    ("type", "shadowsocks".into()),
+                    ("listen", "127.0.0.1".into()),
+                    ("port", setup.server_port.into()),
+                    ("cipher", cipher.clone()),
+                    ("password", password.clone()),
+                    ("udp", true.into()),
+                ])]),
+            ),
+        ]);
+        let client = object([
+            ("mode", "rule".into()),
+            ("mixed-port", 0u16.into()),
+            ("port", 0u16.into()),
+            ("socks-port", setup.client_port.into()),
+            ("allow-lan", false.into()),
+            ("log-level", "error".into()),
+            (
+                "proxies",
+                array([object([
+                    ("name", "ss-out".into()),
+                    ("type", "ss".into()),
+                    ("server", "127.0.0.1".into()),
+                    ("port", setup.server_port.into()),
+                    ("cipher", cipher),
+                    ("password", password),
+                    ("udp", true.into()),
+                ])]),
+            ),
+            (
+                "proxy-groups",
+                array([object([
+                    ("name", "auto".into()),
+                    ("type", "select".into()),
+                    ("proxies", array(["ss-out".into()])),
+                ])]),
+            ),
+            ("rules", array(["MATCH,auto".into()])),
+        ]);
+
+        let run = |role: &str, config: &Value| -> anyhow::Result<Command> {
+            let path = setup.write_config(role, config)?;
+            let mut cmd = Command::new("mihomo");
+            cmd.arg("-f").arg(path);
+            Ok(cmd)
+        };
+        Ok((run("server", &server)?, run("client", &client)?))
+    }
+}
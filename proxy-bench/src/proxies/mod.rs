@@ -0,0 +1,67 @@
+//! Proxy implementations. Each one knows which protocols it speaks and how to
+//! start a server and a SOCKS5 client for them; everything else (ports,
+//! credentials, certificates, process lifecycle) is shared.
+
+mod anytls_rs;
+mod mihomo;
+mod shadowsocks_rust;
+mod sing_box;
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Context as _;
+
+use crate::certs::Cert;
+use crate::protocol::{Protocol, Secrets};
+use bench_payload::json::Value;
+
+pub static ALL: [&dyn Implementation; 4] = [
+    &sing_box::SingBox,
+    &mihomo::Mihomo,
+    &shadowsocks_rust::ShadowsocksRust,
+    &anytls_rs::AnyTlsRs,
+];
+
+pub fn find(name: &str) -> Option<&'static dyn Implementation> {
+    ALL.iter().copied().find(|i| i.name() == name)
+}
+
+/// Everything a case needs to start its two processes.
+pub struct Setup<'a> {
+    /// Where config files go; removed after the run.
+    pub dir: &'a Path,
+    /// File name stem unique to the case.
+    pub stem: String,
+    pub server_port: u16,
+    /// Port of the client's SOCKS5 listener.
+    pub client_port: u16,
+    pub secrets: &'a Secrets,
+    /// Present for protocols that run over TLS.
+    pub cert: Option<&'a Cert>,
+}
+
+impl Setup<'_> {
+    pub fn cert(&self) -> anyhow::Result<&Cert> {
+        self.cert.context("protocol needs a certificate")
+    }
+
+    /// Writes `config` as `{stem}-{role}.json` and returns its path.
+    pub fn write_config(&self, role: &str, config: &Value) -> anyhow::Result<PathBuf> {
+        let path = self.dir.join(format!("{}-{role}.json", self.stem));
+        std::fs::write(&path, config.to_string())
+            .with_context(|| format!("write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+pub trait Implementation: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Protocols it can run, by [`Protocol::kind`].
+    fn protocols(&self) -> &'static [&'static str];
+
+    /// Server and client commands for `protocol`; the client listens for
+    /// SOCKS5 on `setup.client_port`.
+    fn commands(&self, protocol: &Protocol, setup: &Setup) -> anyhow::Result<(Command, Command)>;
+}
@@ -0,0 +1,33 @@
+use std::process::Command;
+
+use super::{Implementation, Setup};
+use crate::protocol::Protocol;
+
+/// `ssserver` and `sslocal` from shadowsocks-rust, configured on the command
+/// line.
+pub struct ShadowsocksRust;
+
+impl Implementation for ShadowsocksRust {
+    fn name(&self) -> &'static str {
+        "shadowsocks-rust"
+    }
+
+    fn protocols(&self) -> &'static [&'static str] {
+        &["shadowsocks"]
+    }
+
+    fn commands(&self, protocol: &Protocol, setup: &Setup) -> anyhow::Result<(Command, Command)> {
+        let Protocol::Shadowsocks { cipher } = protocol else {
+            anyhow::bail!("{} speaks shadowsocks only", self.name());
+        };
+        let server_addr = format!("127.0.0.1:{}", setup.server_port);
+        let password = &setup.secrets.password;
+
+        let mut server = Command::new("ssserver");
+        server.args(["-s", &server_addr, "-m", cipher, "-k", password]);
+        let mut client = Command::new("sslocal");
+        client.args(["-b", &format!("127.0.0.1:{}", setup.client_port)]);
+        client.args(["-s", &server_addr, "-m", cipher, "-k", password]);
+        Ok((server, client))
+    }
+}
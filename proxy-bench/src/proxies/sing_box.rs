@@ -0,0 +1,150 @@
+use std::process::Command;
+
+use super::{Implementation, Setup};
+use crate::certs::SERVER_NAME;
+use crate::protocol::Protocol;
+use bench_payload::json::{Value, array, object};
+
+/// sing-box on both ends, one JSON config per process.
+pub struct SingBox;
+
+impl Implementation for SingBox {
+    fn name(&self) -> &'static str {
+        "sing-box"
+    }
+
+    fn protocols(&self) -> &'static [&'static str] {
+        &["shadowsocks", "trojan", "tuic", "anytls"]
+    }
+
+    fn commands(&self, protocol: &Protocol, setup: &Setup) -> anyhow::Result<(Command, Command)> {
+        let kind = protocol.kind();
+        let password = Value::from(setup.secrets.password.as_str());
+        let mut inbound = vec![
+            ("type", kind.into()),
+            ("tag", format!("{kind}-in").into()),
+            ("listen", "127.0.0.1".into()),
+            ("listen_port", setup.server_port.into()),
+        ];
+        let mut outbound = vec![
+            ("type", kind.into()),
+            ("tag", format!("{kind}-out").into()),
+            ("server", "127.0.0.1".into()),
+            ("server_port", setup.server_port.into()),
+        ];
+        match protocol {
+            Protocol::Shadowsocks { cipher } => {
+                for fields in [&mut inbound, &mut outbound] {
+                    fields.push(("method", cipher.as_str().into()));
+                    fields.push(("password", password.clone()));
+                }
+            }
+            Protocol::Trojan { .. } => {
+                inbound.push(("users", array([object([("password", password.clone())])])));
+                outbound.push(("password", password));
+            }
+            Protocol::Tuic { congestion, .. } => {
+                let uuid = Value::from(setup.secrets.uuid.as_str());
+                inbound.push((
+                    "users",
+                    array([object([
+                        ("uuid", uuid.clone()),
+                        ("password", password.clone()),
+                    ])]),
+                ));
+                inbound.push(("auth_timeout", "3s".into()));
+                outbound.push(("uuid", uuid));
+                outbound.push(("password", password));
+                for fields in [&mut inbound, &mut outbound] {
+                    fields.push(("congestion_control", congestion.as_str().into()));
+                    fields.push(("zero_rtt_handshake", true.into()));
+                }
+            }
+            Protocol::AnyTls { .. } => {
+                inbound.push((
+                    "users",
+                    array([object([
+                        ("name", "sekai".into()),
+                        ("password", password.clone()),
+                    ])]),
+                ));
+                outbound.push(("password", password));
+            }
+        }
+        if protocol.key().is_some() {
+            let cert = setup.cert()?;
+            let alpn = match protocol {
+                Protocol::Trojan { .. } => array(["h2".into(), "http/1.1".into()]),
+                _ => array(["h3".into()]),
+            };
+            inbound.push((
+                "tls",
+                object([
+                    ("enabled", true.into()),
+                    ("server_name", SERVER_NAME.into()),
+                    ("certificate_path", cert.cert.display().to_string().into()),
+                    ("key_path", cert.key.display().to_string().into()),
+                    ("alpn", alpn.clone()),
+                ]),
+            ));
+            outbound.push((
+                "tls",
+                object([
+                    ("enabled", true.into()),
+                    ("server_name", SERVER_NAME.into()),
+                    // 自签名证书
+                    ("insecure", true.into()),
+                    ("alpn", alpn),
+                ]),
+            ));
+        }
+
+        let log = || object([("level", "error".into()), ("timestamp", false.into())]);
+        let direct = || object([("type", "direct".into()), ("tag", "direct".into())]);
+        let server = object([
+            ("log", log()),
+            ("inbounds", array([fields(inbound)])),
+            ("outbounds", array([direct()])),
+        ]);
+        let client = object([
+            ("log", log()),
+            (
+                "inbounds",
+                array([object([
+                    ("type", "socks".into()),
+                    ("tag", "socks-in".into()),
+                    ("listen", "127.0.0.1".into()),
+                    ("listen_port", setup.client_port.into()),
+                ])]),
+            ),
+            ("outbounds", array([fields(outbound), direct()])),
+            (
+                "route",
+                object([(
+                    "rules",
+                    array([object([("outbound", format!("{kind}-out").into())])]),
+                )]),
+            ),
+        ]);
+
+        Ok((
+            run(&setup.write_config("server", &server)?),
+            run(&setup.write_config("client", &client)?),
+        ))
+    }
+}
+
+fn fields(fields: Vec<(&str, Value)>) -> Value {
+    Value::Object(
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value))
+            .collect(),
+    )
+}
+
+fn run(config: &std::path::Path) -> Command {
+    let mut cmd = Command::new("sing-box");
+    cmd.arg("run").arg("-c").arg(config);
+    cmd
+}
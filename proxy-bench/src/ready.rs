@@ -0,0 +1,175 @@
+//! Waiting for a process to come up: polling until it listens, and until a
+//! SOCKS5 client can reach fast-server through it, instead of sleeping and
+//! hoping.
+
+use std::io::{self, Read as _, Write as _};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::process::Process;
+use crate::supervisor;
+
+/// Pause between probes, and how long one connection attempt may take.
+const POLL: Duration = Duration::from_millis(50);
+
+/// How long a proxy may take to answer one SOCKS5 probe.
+const SOCKS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Waits until `process` listens on `port`, over UDP if `udp`.
+pub fn listening(
+    process: &mut Process,
+    port: u16,
+    udp: bool,
+    deadline: Instant,
+) -> anyhow::Result<()> {
+    let what = format!("{} port {port}", if udp { "UDP" } else { "TCP" });
+    poll(process, deadline, &what, || {
+        if udp {
+            udp_bound(port)
+        } else {
+            TcpStream::connect_timeout(&local(port), POLL).map(drop)
+        }
+    })
+}
+
+/// Waits until a SOCKS5 CONNECT through `port` to `target` on localhost
+/// succeeds, which means the client is up and, as far as it lets on, its
+/// server too.
+pub fn socks(
+    process: &mut Process,
+    port: u16,
+    target: u16,
+    deadline: Instant,
+) -> anyhow::Result<()> {
+    let what = format!("SOCKS5 on port {port}");
+    poll(process, deadline, &what, || socks_connect(port, target))
+}
+
+/// Runs `probe` until it succeeds, failing with the process's stderr if it
+/// exits or `deadline` passes first.
+fn poll(
+    process: &mut Process,
+    deadline: Instant,
+    what: &str,
+    mut probe: impl FnMut() -> io::Result<()>,
+) -> anyhow::Result<()> {
+    loop {
+        if supervisor::interrupted() {
+            anyhow::bail!("interrupted");
+        }
+        process.check()?;
+        let e = match probe() {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if Instant::now() >= deadline {
+            return Err(process.error(format!("{what} not ready: {e}")));
+        }
+        thread::sleep(POLL);
+    }
+}
+
+fn local(port: u16) -> SocketAddr {
+    (Ipv4Addr::LOCALHOST, port).into()
+}
+
+fn socks_connect(port: u16, target: u16) -> io::Result<()> {
+    let mut stream = TcpStream::connect_timeout(&local(port), POLL)?;
+    stream.set_read_timeout(Some(SOCKS_TIMEOUT))?;
+    stream.set_write_timeout(Some(SOCKS_TIMEOUT))?;
+
+    // No authentication.
+    stream.write_all(&[5, 1, 0])?;
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply)?;
+    if reply != [5, 0] {
+        return Err(io::Error::other(format!(
+            "greeting answered with {reply:?}"
+        )));
+    }
+    let mut request = vec![5, 1, 0, 1];
+    request.extend(Ipv4Addr::LOCALHOST.octets());
+    request.extend(target.to_be_bytes());
+    stream.write_all(&request)?;
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != 5 {
+        return Err(io::Error::other(format!(
+            "CONNECT answered with version {}",
+            reply[0]
+        )));
+    }
+    if reply[1] != 0 {
+        return Err(io::Error::other(format!(
+            "CONNECT failed with reply {}",
+            reply[1]
+        )));
+    }
+    Ok(())
+}
+
+/// Whether something holds UDP `port`, going by the kernel's socket tables.
+/// Probing by binding the port ourselves could take it from the process.
+#[cfg(target_os = "linux")]
+fn udp_bound(port: u16) -> io::Result<()> {
+    for table in ["/proc/net/udp", "/proc/net/udp6"] {
+        let Ok(table) = std::fs::read_to_string(table) else {
+            continue;
+        };
+        // `sl local_address rem_address ...`, addresses as hex `ip:port`.
+        let bound = table.lines().skip(1).any(|line| {
+            line.split_whitespace()
+                .nth(1)
+                .and_then(|local| local.rsplit_once(':'))
+                .and_then(|(_, p)| u16::from_str_radix(p, 16).ok())
+                == Some(port)
+        });
+        if bound {
+            return Ok(());
+        }
+    }
+    Err(io::Error::other("nothing bound"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn udp_bound(port: u16) -> io::Result<()> {
+    match std::net::UdpSocket::bind(local(port)) {
+        Ok(_) => Err(io::Error::other("nothing bound")),
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    /// A SOCKS5 server that accepts once and answers CONNECT with `reply`.
+    fn proxy(reply: [u8; 4]) -> u16 {
+        let listener = TcpListener::bind(local(0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[5, 0]).unwrap();
+            let mut request = [0; 10];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&reply).unwrap();
+        });
+        port
+    }
+
+    #[test]
+    fn connect_reply() {
+        socks_connect(proxy([5, 0, 0, 1]), 80).unwrap();
+        let e = socks_connect(proxy([5, 5, 0, 1]), 80).unwrap_err();
+        assert_eq!(e.to_string(), "CONNECT failed with reply 5");
+        // Not SOCKS5 at all, even though the status byte says success.
+        let e = socks_connect(proxy([0, 0, 0, 1]), 80).unwrap_err();
+        assert_eq!(e.to_string(), "CONNECT answered with version 0");
+    }
+}
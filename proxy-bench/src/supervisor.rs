@@ -0,0 +1,106 @@
+//! Every child runs in a process group of its own, so a Ctrl-C in the
+//! terminal reaches proxy-bench alone and the whole tree under a proxy can be
+//! signalled at once. Signals are handled on a thread of their own: the first
+//! one terminates every group so the run can wind down and clean up, a second
+//! one kills them and exits on the spot.
+
+use std::io;
+use std::os::unix::process::CommandExt as _;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::{mem, ptr, thread};
+
+static GROUPS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+const SIGNALS: [i32; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+/// Blocks [`SIGNALS`] and starts the thread that waits for them. Has to run
+/// before any other thread starts so that they inherit the mask; children
+/// started through [`isolate`] get theirs cleared.
+pub fn install() -> io::Result<()> {
+    // SAFETY: plain calls on a local signal set.
+    let set = unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in SIGNALS {
+            libc::sigaddset(&mut set, signal);
+        }
+        match libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) {
+            0 => set,
+            e => return Err(io::Error::from_raw_os_error(e)),
+        }
+    };
+    thread::Builder::new()
+        .name("signals".into())
+        .spawn(move || {
+            loop {
+                let mut signal = 0;
+                // SAFETY: `set` outlives the call and `signal` is a valid out
+                // pointer.
+                if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+                    continue;
+                }
+                if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                    signal_all(libc::SIGKILL);
+                    std::process::exit(128 + signal);
+                }
+                eprintln!("\nproxy-bench: interrupted, stopping (again to kill)");
+                signal_all(libc::SIGTERM);
+            }
+        })?;
+    Ok(())
+}
+
+/// Starts the child of `cmd` in a group of its own, with the signals
+/// [`install`] blocks unblocked again.
+pub fn isolate(cmd: &mut Command) {
+    cmd.process_group(0);
+    // SAFETY: only async-signal-safe calls between fork and exec.
+    unsafe {
+        cmd.pre_exec(|| {
+            let mut set: libc::sigset_t = mem::zeroed();
+            libc::sigemptyset(&mut set);
+            for signal in SIGNALS {
+                libc::sigaddset(&mut set, signal);
+            }
+            match libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, ptr::null_mut()) {
+                0 => Ok(()),
+                e => Err(io::Error::from_raw_os_error(e)),
+            }
+        });
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Puts the group led by `pid` on the list a signal takes down.
+pub fn register(pid: u32) {
+    groups().push(pid as i32);
+}
+
+pub fn unregister(pid: u32) {
+    groups().retain(|&g| g != pid as i32);
+}
+
+/// Sends `signal` to every process in the group led by `pid`.
+pub fn signal(pid: u32, signal: i32) {
+    // SAFETY: kill(2) has no memory-safety preconditions; a group that is
+    // gone already just gives ESRCH.
+    unsafe {
+        libc::kill(-(pid as i32), signal);
+    }
+}
+
+fn signal_all(sig: i32) {
+    for &group in groups().iter() {
+        signal(group as u32, sig);
+    }
+}
+
+fn groups() -> std::sync::MutexGuard<'static, Vec<i32>> {
+    GROUPS.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,82 @@
+//! The traffic a case is measured with: one bench-client run, through the
+//! case's SOCKS5 client or straight to fast-server for the baseline.
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::Context as _;
+
+use crate::supervisor;
+use bench_payload::json::Value;
+
+/// A named set of bench-client arguments.
+pub struct Workload {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+/// What bench-client printed.
+pub struct Report {
+    pub json: Value,
+    /// Whether bench-client exited successfully; false if the body came
+    /// back damaged or short.
+    pub intact: bool,
+}
+
+impl Report {
+    /// The number a case is ranked by and its unit: throughput for
+    /// downloads, else requests or connections per second.
+    pub fn headline(&self) -> Option<(f64, &'static str)> {
+        if let Some(mib) = self.json.get("mib_per_sec") {
+            // A number, or the summary of repeated runs.
+            return mib
+                .as_f64()
+                .or_else(|| mib.get("mean")?.as_f64())
+                .map(|v| (v, "MiB/s"));
+        }
+        if let Some(rps) = self.json.get("requests_per_sec") {
+            return rps.as_f64().map(|v| (v, "req/s"));
+        }
+        let cps = self.json.get("connections_per_sec")?;
+        cps.as_f64().map(|v| (v, "conn/s"))
+    }
+}
+
+/// Runs `bench_client` with `args` against fast-server on `port`, through
+/// the SOCKS5 proxy on `proxy` if given.
+pub fn run(
+    bench_client: &Path,
+    args: &[String],
+    port: u16,
+    proxy: Option<u16>,
+) -> anyhow::Result<Report> {
+    let mut cmd = Command::new(bench_client);
+    cmd.env("HTTP_SERVER_PORT", port.to_string()).args(args);
+    if let Some(proxy) = proxy {
+        cmd.args(["-x", &format!("socks5h://127.0.0.1:{proxy}")]);
+    }
+    // A group of its own like the proxies, so an interrupt stops it too.
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    supervisor::isolate(&mut cmd);
+    let child = cmd
+        .spawn()
+        .with_context(|| format!("start {}", bench_client.display()))?;
+    let pid = child.id();
+    supervisor::register(pid);
+    let output = child.wait_with_output();
+    supervisor::unregister(pid);
+    let output = output.context("bench-client")?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let Some(line) = stdout.lines().find(|l| !l.trim().is_empty()) else {
+        anyhow::bail!("{}", stderr.trim());
+    };
+    let json = Value::parse(line).map_err(|e| anyhow::anyhow!("bench-client output: {e}"))?;
+    Ok(Report {
+        json,
+        intact: output.status.success(),
+    })
+}